@@ -1,4 +1,4 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId};
 
 extern crate gamesolver;
 
@@ -217,6 +217,106 @@ fn stack4search_cons(c: &mut Criterion) {
     }));
 }
 
+fn connect4_parallel_search(c: &mut Criterion) {
+    let mut board = Connect4::new();
+    let actions = vec![4, 5, 3, 1, 3, 1, 1, 1, 4, 5, 5, 1, 4, 4, 2, 5];
+    for action in actions {
+        board.play_action(action);
+    }
+    let p = board.cur_player;
+    let evaluator = ConsequtiveEval::new();
+    let mut group = c.benchmark_group("Connect4::ConsequtiveEval, depth=9");
+    for threads in [1, 2, 4, 8] {
+        for (name, parallelism) in [("RootSplit", Parallelism::RootSplit(threads)), ("LazySmp", Parallelism::LazySmp(threads))] {
+            group.bench_with_input(BenchmarkId::new(name, threads), &parallelism, |b, &parallelism| b.iter(|| {
+                black_box(search(&board, SearchParams { parallelism, ..SearchParams::new(TimeControl::Depth(9)) }, 0, &evaluator, p, None))
+            }));
+        }
+    }
+    group.finish();
+}
+
+fn stack4_parallel_search(c: &mut Criterion) {
+    let mut board = Stack4::new();
+    board.play_action((3,3));
+    board.play_action((4,3));
+    let p = board.cur_player;
+    let evaluator = ConsequtiveEval::new();
+    let mut group = c.benchmark_group("Stack4::ConsequtiveEval, depth=4");
+    for threads in [1, 2, 4, 8] {
+        for (name, parallelism) in [("RootSplit", Parallelism::RootSplit(threads)), ("LazySmp", Parallelism::LazySmp(threads))] {
+            group.bench_with_input(BenchmarkId::new(name, threads), &parallelism, |b, &parallelism| b.iter(|| {
+                black_box(search(&board, SearchParams { parallelism, ..SearchParams::new(TimeControl::Depth(4)) }, 0, &evaluator, p, None))
+            }));
+        }
+    }
+    group.finish();
+}
+
+// ConsequtiveEval::new() values every position at 0, which makes move ordering look better than it is.
+fn weighted_eval() -> ConsequtiveEval {
+    ConsequtiveEval { params: vec![0.1, 0.4, 1.3, -0.2, -0.5, -1.1] }
+}
+
+// Benchmarks the search of 'board' with every variant of the options and prints the number of nodes,
+// which unlike the time does not depend on the machine.
+fn compare_options<G>(c: &mut Criterion, name: &str, board: &G, depth: u32, variants: &[(&str, SearchOptions)]) 
+    where
+        G: Game,
+        ConsequtiveEval: Evaluator<G>,
+{
+    let evaluator = weighted_eval();
+    let p = board.cur_player();
+    let mut group = c.benchmark_group(name);
+    for &(variant, options) in variants {
+        let stats = search(board, SearchParams { options, ..SearchParams::new(TimeControl::Depth(depth)) }, 0, &evaluator, p, None).stats;
+        println!("{} {}: {}", name, variant, stats);
+        group.bench_function(variant, |b| b.iter(|| {
+            black_box(search(board, SearchParams { options, ..SearchParams::new(TimeControl::Depth(depth)) }, 0, &evaluator, p, None))
+        }));
+    }
+    group.finish();
+}
+
+fn stack4_move_ordering(c: &mut Criterion) {
+    let mut board = Stack4::new();
+    board.play_action((3,0));
+    board.play_action((4,0));
+    let killers = SearchOptions { killer_moves: true, ..SearchOptions::default() };
+    let both = SearchOptions { history: true, ..killers };
+    compare_options(c, "Stack4::ConsequtiveEval move ordering, depth=4", &board, 4, 
+        &[("plain", SearchOptions::default()), ("killers", killers), ("killers+history", both)]);
+}
+
+fn search_windows(c: &mut Criterion) {
+    let pvs = SearchOptions { pvs: true, ..SearchOptions::default() };
+    let aspiration = SearchOptions { aspiration_window: Some(0.25), ..SearchOptions::default() };
+    let both = SearchOptions { pvs: true, ..aspiration };
+    let variants = [("alpha-beta", SearchOptions::default()), ("pvs", pvs), ("aspiration", aspiration), ("pvs+aspiration", both)];
+
+    // the score is not a win or loss so the aspiration window is used in every iteration.
+    let mut board = Connect4::new();
+    for action in [4, 5, 3, 1, 3, 1, 1, 1] {
+        board.play_action(action);
+    }
+    compare_options(c, "Connect4::ConsequtiveEval windows, depth=9", &board, 9, &variants);
+
+    let mut board = Stack4::new();
+    board.play_action((3,0));
+    board.play_action((4,0));
+    compare_options(c, "Stack4::ConsequtiveEval windows, depth=4", &board, 4, &variants);
+}
+
+fn threat_extension(c: &mut Criterion) {
+    let extension = SearchOptions { threat_extension: 4, ..SearchOptions::default() };
+    let variants = [("no extension", SearchOptions::default()), ("threat extension 4", extension)];
+    let mut board = Connect4::new();
+    for action in [4, 5, 3, 1, 3, 1, 1, 1, 4, 5, 5, 1, 4, 4, 2, 5] {
+        board.play_action(action);
+    }
+    compare_options(c, "Connect4::ConsequtiveEval threat extension, depth=7", &board, 7, &variants);
+}
+
 fn stack4_player_won(c: &mut Criterion) {
     let mut board = Stack4::new();
     board.play_action((3,3));
@@ -251,6 +351,11 @@ criterion_group!(
     cnn_search_batch,
     stack4search,
     stack4search_cons,
+    connect4_parallel_search,
+    stack4_parallel_search,
+    stack4_move_ordering,
+    search_windows,
+    threat_extension,
     stack4_player_won,
     connct4_player_won,
 );
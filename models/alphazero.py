@@ -0,0 +1,51 @@
+#!/usr/bin/env python3
+# Creates a policy+value model for 'gametrainer alpha-zero'.
+# usage: models/alphazero.py connect4|stack4|othello
+import sys
+import torch
+from torch.nn import Module
+
+games = {
+    # shape of the board as given by Game::shape() and number of actions.
+    "connect4": ((7, 6), 7),
+    "stack4": ((8, 8), 64),
+    # the last action is a pass.
+    "othello": ((8, 8), 65),
+}
+
+class PolicyValueModule(Module):
+    def __init__(self, shape, actions):
+        super().__init__()
+        width, height = shape
+        self.conv1 = torch.nn.Conv2d(1, 32, kernel_size=(3, 3), padding=(1,1))
+        self.conv2 = torch.nn.Conv2d(32, 32, kernel_size=(3, 3), padding=(1,1))
+        self.flatten = torch.nn.Flatten()
+        self.policy_conv = torch.nn.Conv2d(32, 2, kernel_size=(1, 1))
+        self.policy_linear = torch.nn.Linear(2 * width * height, actions)
+        self.value_conv = torch.nn.Conv2d(32, 1, kernel_size=(1, 1))
+        self.value_linear1 = torch.nn.Linear(width * height, 32)
+        self.value_linear2 = torch.nn.Linear(32, 1)
+
+    def forward(self, x):
+        x = torch.relu(self.conv1(x))
+        x = torch.relu(self.conv2(x))
+        # logits, the softmax is done by the caller.
+        p = torch.relu(self.policy_conv(x))
+        p = self.policy_linear(self.flatten(p))
+        v = torch.relu(self.value_conv(x))
+        v = torch.relu(self.value_linear1(self.flatten(v)))
+        v = torch.tanh(self.value_linear2(v))
+        return p, v
+
+
+game = sys.argv[1] if len(sys.argv) > 1 else "connect4"
+shape, actions = games[game]
+
+a = torch.zeros((5, 1) + shape).double()
+net = PolicyValueModule(shape, actions)
+net = net.double()
+p, v = net(a)
+print(p.size(), v.size())
+
+traced_script_module = torch.jit.script(net)
+traced_script_module.save(f"models/{game}_alphazero.pt")
@@ -1,5 +1,5 @@
 
-use crate::evaluators::{Evaluator};
+use crate::evaluators::{Evaluator, SimpleEval};
 use crate::search::*;
 use crate::games::{Player, Game};
 use crate::policies::Policy;
@@ -12,19 +12,35 @@ pub trait Agent<G>
     fn get_action_explored(&self, board: &G, player: Player) -> (G::Action, bool) {
         (self.get_action(board, player), false)
     }
+    // The search behind the chosen action, for agents that search.
+    fn analyse(&self, _board: &G, _player: Player) -> Option<SearchResult<G::Action>> {
+        None
+    }
+    // Who played a game, saved in game records.
+    fn description(&self) -> String {
+        std::any::type_name::<Self>().to_string()
+    }
 }
 
 
 pub struct MinimaxAgent<'a, T> {
     evaluator: &'a T,
-    depth: u32,
+    time_control: TimeControl,
+    pub parallelism: Parallelism,
+    pub options: SearchOptions,
 }
 
 impl<'a, T> MinimaxAgent<'a, T> {
     pub fn new(evaluator: &'a T, depth: u32) -> Self {
+        MinimaxAgent::with_time_control(evaluator, TimeControl::Depth(depth))
+    }
+
+    pub fn with_time_control(evaluator: &'a T, time_control: TimeControl) -> Self {
         MinimaxAgent::<T> {
             evaluator,
-            depth,
+            time_control,
+            parallelism: Parallelism::Single,
+            options: SearchOptions::default(),
         }
     }
 }
@@ -37,22 +53,44 @@ impl<'a, T, G> Agent<G> for MinimaxAgent<'a, T>
 {
     fn get_action(&self, board: &G, player: Player) -> G::Action 
     {
-        abnegamax_best_action(board, self.depth, self.evaluator, player)
+        // the fixed depth searches always use the default options.
+        match (self.time_control, self.parallelism) {
+            _ if self.options != SearchOptions::default() => self.analyse(board, player).unwrap().best_action,
+            (TimeControl::Depth(depth), Parallelism::Single) => abnegamax_best_action(board, depth, self.evaluator, player),
+            (TimeControl::Depth(depth), Parallelism::RootSplit(threads)) => {
+                parallel_abnegamax_best_action(board, depth, self.evaluator, player, threads)
+            },
+            (tc, parallelism) => search(board, SearchParams { parallelism, options: self.options, ..SearchParams::new(tc) }, 0, self.evaluator, player, None).best_action,
+        }
+    }
+
+    fn analyse(&self, board: &G, player: Player) -> Option<SearchResult<G::Action>> {
+        Some(search(board, SearchParams { parallelism: self.parallelism, options: self.options, ..SearchParams::new(self.time_control) }, 0, self.evaluator, player, None))
+    }
+
+    fn description(&self) -> String {
+        format!("minimax {:?}", self.time_control)
     }
 }
 
 pub struct BatchMinimaxAgent<'a, T> {
     evaluator: &'a T,
-    depth: u32,
+    time_control: TimeControl,
     batch_depth: u32,
+    pub options: SearchOptions,
 }
 
 impl<'a, T> BatchMinimaxAgent<'a, T> {
     pub fn new(evaluator: &'a T, depth: u32, batch_depth: u32) -> Self {
+        BatchMinimaxAgent::with_time_control(evaluator, TimeControl::Depth(depth), batch_depth)
+    }
+
+    pub fn with_time_control(evaluator: &'a T, time_control: TimeControl, batch_depth: u32) -> Self {
         BatchMinimaxAgent::<T> {
             evaluator,
-            depth,
+            time_control,
             batch_depth,
+            options: SearchOptions::default(),
         }
     }
 }
@@ -64,7 +102,19 @@ impl<'a, T, G> Agent<G> for BatchMinimaxAgent<'a, T>
         T: Evaluator<G>
 {
     fn get_action(&self, board: &G, player: Player) -> G::Action {
-        batch_abnegamax_best_action(board, self.depth, self.batch_depth, self.evaluator, player)
+        match self.time_control {
+            _ if self.options != SearchOptions::default() => self.analyse(board, player).unwrap().best_action,
+            TimeControl::Depth(depth) => batch_abnegamax_best_action(board, depth, self.batch_depth, self.evaluator, player),
+            tc => iterative_deepening_best_action(board, tc, self.batch_depth, self.evaluator, player, None),
+        }
+    }
+
+    fn analyse(&self, board: &G, player: Player) -> Option<SearchResult<G::Action>> {
+        Some(search(board, SearchParams { options: self.options, ..SearchParams::new(self.time_control) }, self.batch_depth, self.evaluator, player, None))
+    }
+
+    fn description(&self) -> String {
+        format!("batch minimax {:?} batch depth {}", self.time_control, self.batch_depth)
     }
 }
 
@@ -74,6 +124,7 @@ pub struct MinimaxPolicyAgent<'a, T> {
     policy: &'a dyn Policy,
     depth: u32,
     pub batch_depth: u32,
+    pub options: SearchOptions,
 }
 
 impl<'a, T> MinimaxPolicyAgent<'a, T> {
@@ -82,7 +133,8 @@ impl<'a, T> MinimaxPolicyAgent<'a, T> {
             evaluator,
             policy,
             depth,
-            batch_depth:0
+            batch_depth:0,
+            options: SearchOptions::default(),
         }
     }
 }
@@ -106,11 +158,11 @@ impl<'a, T, G> Agent<G> for MinimaxPolicyAgent<'a, T>
         let mut winning_moves = Vec::new();
         let mut avs = Vec::new();
         let actions:Vec<_> = board.legal_actions().collect();
-        let mut tt = TranspositionTable::new();
+        let tt = TranspositionTable::new();
         for action in &actions {
-            board.play_action(*action);
-            let v = -abnegamax(&board, self.depth-1, self.batch_depth, self.evaluator, !player, Some(&mut tt));
-            board.reverse_last_action(*action);
+            let undo = board.play_action(*action);
+            let v = -abnegamax(&board, self.depth-1, self.batch_depth, self.evaluator, !player, Some(&tt), self.options);
+            board.reverse_last_action(undo);
             if v == 1./0. {
                 winning_moves.push(action);
             } else if v != -1./0. {
@@ -128,89 +180,139 @@ impl<'a, T, G> Agent<G> for MinimaxPolicyAgent<'a, T>
             return (actions[fastrand::usize(0..actions.len())], false);
         }
     }
+
+    fn description(&self) -> String {
+        format!("minimax with policy depth {}", self.depth)
+    }
 }
 
-pub struct CompositeAgent<'a, T> {
+// S is the evaluator of the search for wins and losses, SimpleEval unless
+// it is replaced, for example to probe an endgame database.
+pub struct CompositeAgent<'a, T, S = SimpleEval> {
     evaluator: &'a T,
-    depth: u32,
+    simple_eval: S,
+    time_control: TimeControl, // limits the search with self.evaluator, the search with SimpleEval always has a fixed depth.
     pub simple_depth: u32, // how deep it should search with SimpleEval.
     pub batch_depth: u32,
+    pub parallelism: Parallelism,
+    pub options: SearchOptions,
 }
 
 impl<'a, T> CompositeAgent<'a, T> {
     pub fn new(evaluator: &'a T, depth: u32, batch_depth: u32, simple_depth: u32) -> Self {
+        CompositeAgent::with_time_control(evaluator, TimeControl::Depth(depth), batch_depth, simple_depth)
+    }
+
+    pub fn with_time_control(evaluator: &'a T, time_control: TimeControl, batch_depth: u32, simple_depth: u32) -> Self {
         CompositeAgent::<T> {
             evaluator,
-            depth,
+            simple_eval: SimpleEval::new(),
+            time_control,
             simple_depth,
-            batch_depth
+            batch_depth,
+            parallelism: Parallelism::Single,
+            options: SearchOptions::default(),
         }
     }
 }
 
+impl<'a, T, S> CompositeAgent<'a, T, S> {
+    pub fn with_simple_eval<U>(self, simple_eval: U) -> CompositeAgent<'a, T, U> {
+        CompositeAgent {
+            evaluator: self.evaluator,
+            simple_eval,
+            time_control: self.time_control,
+            simple_depth: self.simple_depth,
+            batch_depth: self.batch_depth,
+            parallelism: self.parallelism,
+            options: self.options,
+        }
+    }
+}
 
-impl<'a, T, G> Agent<G> for CompositeAgent<'a, T> 
+impl<'a, T, S, G> Agent<G> for CompositeAgent<'a, T, S> 
     where
         G: Game,
         G::Action: Copy,
-        T: Evaluator<G>
+        T: Evaluator<G>,
+        S: Evaluator<G>,
 {
     // Searches at depth self.simple_depth using SimpleEval to determine losing and winning moves.
     // If there is a winning move the move will be played. 
     // If there are any moves that are unclear (first search found no win or loss for these moves) 
-    // their heuristic value will be computed using self.evaluator at depth self.depth and the action
+    // their heuristic value will be computed using self.evaluator within self.time_control and the action
     // with maximum value will be played.
     fn get_action(&self, board: &G, player: Player) -> G::Action {
-        
-        let mut board = board.clone();
-
-        let mut winning_actions = Vec::new();
-        let mut losing_actions = Vec::new();
-        let mut unclear_actions = Vec::new(); // actions where the search with SimpleEval returned 0.0 (heuristic value or draw).
+        self.analyse(board, player).unwrap().best_action
+    }
 
+    fn analyse(&self, board: &G, player: Player) -> Option<SearchResult<G::Action>> {
         let actions: Vec<G::Action> = board.legal_actions().collect();
-        let mut tt = TranspositionTable::new();
-        
-        let simple_eval = crate::evaluators::SimpleEval::new();
+        let tt = TranspositionTable::new();
+        let params = |time_control| SearchParams { parallelism: self.parallelism, options: self.options, ..SearchParams::new(time_control) };
+        // the unclear actions are told apart by their exact values, so the simple search has no aspiration window.
+        let simple_params = SearchParams { options: SearchOptions { aspiration_window: None, ..self.options }, ..params(TimeControl::Depth(self.simple_depth)) };
+        let simple = iterative_deepening(board, actions, simple_params, 0, &self.simple_eval, player, &tt);
 
-        for action in actions {
-            board.play_action(action);
-            let v = -abnegamax(&board, self.simple_depth-1, 0, &simple_eval, !player, Some(&mut tt));
-            board.reverse_last_action(action);
-            if v > 0.0 {
-                winning_actions.push((action, v));
-            } else if v < 0.0 {
-                losing_actions.push((action, v));
-            } else {
-                unclear_actions.push(action);
-            }
-        }
-        if !winning_actions.is_empty() {
-            winning_actions.sort_by(|(_,v1), (_,v2)| v2.partial_cmp(v1).unwrap());
-            return winning_actions[0].0;
-        } else if !unclear_actions.is_empty() {
-            let mut tt = TranspositionTable::new();
-            let mut avs = Vec::new();
-            for action in unclear_actions {
-                board.play_action(action);
-                let v = abnegamax(&board, self.depth-1, self.batch_depth, self.evaluator, player, Some(&mut tt));
-                board.reverse_last_action(action);
-                avs.push((action, v));
-            }
-            avs.sort_by(|(_,v1), (_,v2)| v2.partial_cmp(v1).unwrap());
-            return avs[0].0;
-        } else if !losing_actions.is_empty() {
-            losing_actions.sort_by(|(_,v1), (_,v2)| v2.partial_cmp(v1).unwrap());
-            return losing_actions[0].0;
-        } else {
-            panic!("no action selected!")
+        // actions where the search with SimpleEval returned 0.0 (heuristic value or draw).
+        let unclear_actions: Vec<G::Action> = simple.root_scores.iter().filter(|(_,v)| *v == 0.0).map(|(a,_)| *a).collect();
+        if simple.score > 0.0 || unclear_actions.is_empty() {
+            return Some(simple);
         }
+        // each unclear action is valued as the negation of the opponent's best reply, the same way as
+        // the root of any search.
+        let tt = TranspositionTable::new();
+        let mut result = iterative_deepening(board, unclear_actions, params(self.time_control), self.batch_depth, self.evaluator, player, &tt);
+        result.stats += simple.stats;
+        result.root_scores.extend(simple.root_scores.into_iter().filter(|(_,v)| *v < 0.0));
+        Some(result)
     }
 
     // Returns chosen action and a boolean that is true if it was a exploring move
     fn get_action_explored(&self, board: &G, player: Player) -> (G::Action, bool) {
         (self.get_action(board, player), false)
     }
+
+    fn description(&self) -> String {
+        format!("composite {:?} simple depth {}", self.time_control, self.simple_depth)
+    }
 }
 
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::evaluators::ConsequtiveEval;
+    use crate::games::connect4::Connect4;
+    use crate::search::{abnegamax, negamax};
+
+    #[test]
+    fn composite_unclear_actions() {
+        let evaluator = ConsequtiveEval { params: vec![0.1, 0.4, 1.3, -0.2, -0.5, -1.1] };
+        let agent = CompositeAgent::new(&evaluator, 3, 0, 2);
+        let mut board = Connect4::new();
+        for action in [5, 2, 4, 3, 4, 1, 4, 6] {
+            board.play_action(action);
+        }
+        let player = board.cur_player();
+        let simple = search(&board, SearchParams::new(TimeControl::Depth(2)), 0, &SimpleEval::new(), player, None);
+        let unclear: Vec<_> = simple.root_scores.iter().filter(|(_, v)| *v == 0.0).map(|(a, _)| *a).collect();
+        let value = |action| {
+            let mut board = board;
+            board.play_action(action);
+            -negamax(&mut board, 2, &evaluator, !player)
+        };
+        // the unclear actions used to be scored by a search for 'player' in the position where the opponent
+        // is to move, which assumes that the opponent replies with the move that is best for 'player'.
+        let old_score = |action| {
+            let mut board = board;
+            board.play_action(action);
+            abnegamax(&board, 2, 0, &evaluator, player, None, SearchOptions::default())
+        };
+        let old = unclear.iter().copied().max_by(|&a, &b| old_score(a).partial_cmp(&old_score(b)).unwrap()).unwrap();
+        assert_eq!(value(old), -1./0.);
+        let action = agent.get_action(&board, player);
+        assert_eq!(value(action), 1./0.);
+    }
+}
@@ -0,0 +1,428 @@
+use crate::agents::Agent;
+use crate::games::{Player, GameState, Game};
+use anyhow::{anyhow, Result};
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use tch::nn::{OptimizerConfig, Optimizer, VarStore};
+use tch::{Device, IValue, Kind, Tensor, TrainableCModule};
+
+// Anything that gives prior probabilities for the actions and a value of a position.
+pub trait PolicyValue<G: Game> {
+    // For every board the probabilities of all G::action_space() actions and
+    // the value in [-1, 1] for the player to move.
+    fn predict(&self, boards: &[G]) -> Vec<(Vec<f64>, f64)>;
+}
+
+// A TorchScript model, created by models/alphazero.py, that maps a batch of boards
+// to policy logits over all actions and values.
+pub struct PolicyValueNet {
+    pub model: TrainableCModule,
+    pub vs: VarStore,
+}
+
+impl PolicyValueNet {
+    pub fn new(model_path: &str) -> Result<PolicyValueNet> {
+        let vs = VarStore::new(Device::Cpu);
+        let mut model = TrainableCModule::load(model_path, vs.root())?;
+        model.set_eval();
+        Ok(PolicyValueNet {
+            model,
+            vs,
+        })
+    }
+
+    pub fn save(&self, model_path: &str) -> Result<()> {
+        self.model.save(model_path)?;
+        Ok(())
+    }
+
+    fn input<G: Game>(boards: &[G]) -> Tensor {
+        let mut vectorized_boards = Vec::with_capacity(boards.len()*G::shape()[0]*G::shape()[1]);
+        for board in boards {
+            vectorized_boards.append(&mut board.vectorize(board.cur_player()));
+        }
+        PolicyValueNet::input_from_slice::<G>(&vectorized_boards)
+    }
+
+    fn input_from_slice<G: Game>(vectorized_boards: &[f64]) -> Tensor {
+        let shape = G::shape();
+        let n = vectorized_boards.len()/(shape[0]*shape[1]);
+        Tensor::of_slice(vectorized_boards).view([n as i64, 1, shape[0] as i64, shape[1] as i64])
+    }
+
+    // Returns the policy logits and the values.
+    fn forward(&self, input: Tensor) -> Result<(Tensor, Tensor)> {
+        match self.model.forward_is(&[IValue::Tensor(input)])? {
+            IValue::Tuple(outputs) => match <[IValue; 2]>::try_from(outputs) {
+                Ok([IValue::Tensor(policy), IValue::Tensor(value)]) => Ok((policy, value)),
+                _ => Err(anyhow!("the model has to return two tensors")),
+            },
+            _ => Err(anyhow!("the model has to return a (policy, value) tuple")),
+        }
+    }
+}
+
+impl<G: Game> PolicyValue<G> for PolicyValueNet {
+    fn predict(&self, boards: &[G]) -> Vec<(Vec<f64>, f64)> {
+        let _guard = tch::no_grad_guard();
+        let (policy, value) = self.forward(PolicyValueNet::input(boards)).unwrap();
+        let policy: Vec<f64> = Vec::from(policy.softmax(-1, Kind::Double));
+        let value: Vec<f64> = Vec::from(value);
+        let n = G::action_space();
+        (0..boards.len()).map(|i| (policy[i*n..(i+1)*n].to_vec(), value[i])).collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AlphaZeroConfig {
+    pub simulations: u32, // MCTS simulations per move.
+    pub c_puct: f64,
+    pub dirichlet_alpha: f64,
+    pub noise_fraction: f64, // how much of the root priors in self-play is replaced by noise.
+    pub temperature_moves: u32, // the first moves of self-play are sampled in proportion to the visit counts.
+    pub batch_size: usize,
+    pub learning_rate: f64,
+    pub buffer_size: usize,
+}
+
+impl Default for AlphaZeroConfig {
+    fn default() -> Self {
+        AlphaZeroConfig {
+            simulations: 100,
+            c_puct: 1.5,
+            dirichlet_alpha: 0.5,
+            noise_fraction: 0.25,
+            temperature_moves: 8,
+            batch_size: 64,
+            learning_rate: 0.001,
+            buffer_size: 20000,
+        }
+    }
+}
+
+struct Node<A> {
+    action: Option<A>,
+    children: Vec<usize>,
+    prior: f64,
+    visits: u32,
+    value_sum: f64, // from the perspective of the player that played 'action'.
+}
+
+// PUCT search as in AlphaZero, returns the visit count of every action at the root.
+pub fn puct_search<G, P>(board: &G, net: &P, config: &AlphaZeroConfig, add_noise: bool) -> Vec<(G::Action, u32)>
+    where
+        G: Game,
+        P: PolicyValue<G>,
+{
+    let mut nodes = vec![Node { action: None, children: Vec::new(), prior: 1.0, visits: 0, value_sum: 0.0 }];
+    expand(&mut nodes, 0, board, net);
+    if add_noise {
+        let noise = dirichlet(config.dirichlet_alpha, nodes[0].children.len());
+        for (&c, eta) in nodes[0].children.clone().iter().zip(noise) {
+            nodes[c].prior = (1.0-config.noise_fraction)*nodes[c].prior + config.noise_fraction*eta;
+        }
+    }
+    for _ in 0..config.simulations {
+        let mut b = *board;
+        let mut path = vec![0];
+        let mut i = 0;
+        while !nodes[i].children.is_empty() {
+            let sqrt_visits = (nodes[i].visits as f64).sqrt();
+            i = *nodes[i].children.iter().max_by(|&&a, &&b| {
+                puct(&nodes[a], sqrt_visits, config.c_puct).partial_cmp(&puct(&nodes[b], sqrt_visits, config.c_puct)).unwrap()
+            }).unwrap();
+            b.play_action(nodes[i].action.unwrap());
+            path.push(i);
+        }
+        // value for the player to move in 'b'.
+        let mut value = match b.game_state() {
+            GameState::InProgress => expand(&mut nodes, i, &b, net),
+            GameState::Draw => 0.0,
+            GameState::Won(_) => -1.0,
+        };
+        for &j in path.iter().rev() {
+            nodes[j].visits += 1;
+            nodes[j].value_sum -= value;
+            value = -value;
+        }
+    }
+    nodes[0].children.iter().map(|&c| (nodes[c].action.unwrap(), nodes[c].visits)).collect()
+}
+
+fn puct<A>(node: &Node<A>, sqrt_parent_visits: f64, c_puct: f64) -> f64 {
+    let q = if node.visits == 0 { 0.0 } else { node.value_sum/node.visits as f64 };
+    q + c_puct*node.prior*sqrt_parent_visits/(1+node.visits) as f64
+}
+
+// Adds the legal actions of 'board' as children of node 'i' and returns the value of 'board'.
+fn expand<G, P>(nodes: &mut Vec<Node<G::Action>>, i: usize, board: &G, net: &P) -> f64
+    where
+        G: Game,
+        P: PolicyValue<G>,
+{
+    let (policy, value) = net.predict(&[*board]).pop().unwrap();
+    let actions: Vec<G::Action> = board.legal_actions().collect();
+    // the probabilities of illegal actions are given to the legal ones.
+    let total: f64 = actions.iter().map(|&a| policy[G::action_index(a)]).sum();
+    for action in actions.iter() {
+        let prior = if total > 0.0 {
+            policy[G::action_index(*action)]/total
+        } else {
+            1.0/actions.len() as f64
+        };
+        nodes.push(Node { action: Some(*action), children: Vec::new(), prior, visits: 0, value_sum: 0.0 });
+        let child = nodes.len()-1;
+        nodes[i].children.push(child);
+    }
+    value
+}
+
+fn dirichlet(alpha: f64, n: usize) -> Vec<f64> {
+    let samples: Vec<f64> = (0..n).map(|_| gamma(alpha)).collect();
+    let total: f64 = samples.iter().sum();
+    samples.iter().map(|x| x/total).collect()
+}
+
+// Marsaglia and Tsang's method, shape parameters below one are boosted by U^(1/alpha).
+fn gamma(alpha: f64) -> f64 {
+    if alpha < 1.0 {
+        return gamma(alpha+1.0)*fastrand::f64().powf(1.0/alpha);
+    }
+    let d = alpha - 1.0/3.0;
+    let c = 1.0/(9.0*d).sqrt();
+    loop {
+        // standard normal with the Box-Muller transform.
+        let x = (-2.0*(1.0-fastrand::f64()).ln()).sqrt()*(2.0*std::f64::consts::PI*fastrand::f64()).cos();
+        let v = (1.0+c*x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u = 1.0-fastrand::f64();
+        if u.ln() < 0.5*x*x + d - d*v + d*v.ln() {
+            return d*v;
+        }
+    }
+}
+
+// A position from self-play together with the training targets.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub board: Vec<f64>, // vectorized for the player to move.
+    pub policy: Vec<f64>, // visit count distribution over all actions.
+    pub value: f64, // outcome of the game for the player to move.
+}
+
+// Keeps the last 'capacity' samples.
+#[derive(Serialize, Deserialize)]
+pub struct ReplayBuffer {
+    capacity: usize,
+    samples: VecDeque<Sample>,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> ReplayBuffer {
+        ReplayBuffer {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, sample: Sample) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    // Drops the oldest samples that don't fit in the new capacity, used when a buffer is loaded with
+    // another buffer size than it was saved with.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.samples.len() > capacity {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    // 'n' samples drawn uniformly with replacement.
+    pub fn sample(&self, n: usize) -> Vec<&Sample> {
+        (0..n).map(|_| &self.samples[fastrand::usize(0..self.samples.len())]).collect()
+    }
+}
+
+// Plays a game against itself and returns a sample for every position.
+pub fn self_play_game<G, P>(net: &P, config: &AlphaZeroConfig) -> Vec<Sample>
+    where
+        G: Game,
+        P: PolicyValue<G>,
+{
+    let mut board = G::new();
+    let mut positions = Vec::new();
+    while board.game_state() == GameState::InProgress {
+        let visits = puct_search(&board, net, config, true);
+        let total: u32 = visits.iter().map(|(_,n)| n).sum();
+        let mut policy = vec![0.0; G::action_space()];
+        for &(action, n) in &visits {
+            policy[G::action_index(action)] = n as f64/total as f64;
+        }
+        let action = if board.length() < config.temperature_moves {
+            let mut r = fastrand::u32(0..total);
+            visits.iter().find(|(_,n)| if r < *n { true } else { r -= n; false }).unwrap().0
+        } else {
+            visits.iter().max_by_key(|(_,n)| *n).unwrap().0
+        };
+        positions.push((board.vectorize(board.cur_player()), policy, board.cur_player()));
+        board.play_action(action);
+    }
+    positions.into_iter().map(|(vectorized, policy, player)| Sample {
+        board: vectorized,
+        policy,
+        value: match board.game_state() {
+            GameState::Won(p) if p == player => 1.0,
+            GameState::Won(_) => -1.0,
+            _ => 0.0,
+        },
+    }).collect()
+}
+
+// Self-play with PUCT and training of a PolicyValueNet on the visit counts and game outcomes.
+pub struct AlphaZero {
+    pub net: PolicyValueNet,
+    pub buffer: ReplayBuffer,
+    pub config: AlphaZeroConfig,
+    optimizer: Optimizer,
+}
+
+impl AlphaZero {
+    pub fn new(net: PolicyValueNet, config: AlphaZeroConfig) -> Result<AlphaZero> {
+        let optimizer = tch::nn::adam(0.9, 0.999, 0.0).build(&net.vs, config.learning_rate)?;
+        Ok(AlphaZero {
+            net,
+            buffer: ReplayBuffer::new(config.buffer_size),
+            config,
+            optimizer,
+        })
+    }
+
+    pub fn self_play<G: Game>(&mut self, games: u32) {
+        for _ in 0..games {
+            for sample in self_play_game::<G, _>(&self.net, &self.config) {
+                self.buffer.push(sample);
+            }
+        }
+    }
+
+    // One gradient step on a batch from the replay buffer, returns the loss.
+    pub fn train_step<G: Game>(&mut self) -> f64 {
+        let batch = self.buffer.sample(self.config.batch_size);
+        let boards: Vec<f64> = batch.iter().flat_map(|s| s.board.iter().copied()).collect();
+        let policies: Vec<f64> = batch.iter().flat_map(|s| s.policy.iter().copied()).collect();
+        let values: Vec<f64> = batch.iter().map(|s| s.value).collect();
+        let n = batch.len() as i64;
+        let target_policy = Tensor::of_slice(&policies).view([n, G::action_space() as i64]);
+        let target_value = Tensor::of_slice(&values).view([n, 1]);
+
+        self.net.model.set_train();
+        let (policy, value) = self.net.forward(PolicyValueNet::input_from_slice::<G>(&boards)).unwrap();
+        // cross entropy, the mean is over every element so it is scaled up by the number of actions.
+        let policy_loss = -(&target_policy * &policy.log_softmax(-1, Kind::Double)).mean(Kind::Double) * G::action_space() as f64;
+        let value_loss = value.mse_loss(&target_value, tch::Reduction::Mean);
+        let loss = policy_loss + value_loss;
+        self.optimizer.backward_step(&loss);
+        self.net.model.set_eval();
+        loss.double_value(&[])
+    }
+}
+
+// Plays the most visited action of a PUCT search without noise.
+pub struct AlphaZeroAgent<'a, P> {
+    net: &'a P,
+    pub config: AlphaZeroConfig,
+}
+
+impl<'a, P> AlphaZeroAgent<'a, P> {
+    pub fn new(net: &'a P, config: AlphaZeroConfig) -> Self {
+        AlphaZeroAgent {
+            net,
+            config,
+        }
+    }
+}
+
+impl<'a, P, G> Agent<G> for AlphaZeroAgent<'a, P>
+    where
+        G: Game,
+        P: PolicyValue<G>,
+{
+    fn get_action(&self, board: &G, _player: Player) -> G::Action {
+        let visits = puct_search(board, self.net, &self.config, false);
+        visits.iter().max_by_key(|(_,n)| *n).unwrap().0
+    }
+
+    fn description(&self) -> String {
+        format!("alphazero {} simulations", self.config.simulations)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::games::connect4::Connect4;
+
+    // Uniform priors and no knowledge about the value, the search only sees terminal positions.
+    struct Uniform;
+
+    impl<G: Game> PolicyValue<G> for Uniform {
+        fn predict(&self, boards: &[G]) -> Vec<(Vec<f64>, f64)> {
+            boards.iter().map(|_| (vec![1.0/G::action_space() as f64; G::action_space()], 0.0)).collect()
+        }
+    }
+
+    #[test]
+    fn puct_finds_win() {
+        let config = AlphaZeroConfig { simulations: 400, ..Default::default() };
+        let mut board = Connect4::new();
+        for action in [3, 0, 3, 0, 3, 1] {
+            board.play_action(action);
+        }
+        let agent = AlphaZeroAgent::new(&Uniform, config);
+        assert_eq!(agent.get_action(&board, board.cur_player()), 3);
+    }
+
+    #[test]
+    fn self_play_samples() {
+        let config = AlphaZeroConfig { simulations: 20, buffer_size: 30, ..Default::default() };
+        let samples = self_play_game::<Connect4, _>(&Uniform, &config);
+        let last = samples.last().unwrap();
+        // the last move always wins or draws.
+        assert!(last.value >= 0.0);
+        for (i, sample) in samples.iter().enumerate() {
+            assert!((sample.policy.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+            assert_eq!(sample.board.len(), 42);
+            assert_eq!(sample.value, if (samples.len()-1-i) % 2 == 0 { last.value } else { -last.value });
+        }
+        let mut buffer = ReplayBuffer::new(config.buffer_size);
+        for _ in 0..4 {
+            for sample in samples.iter().cloned() {
+                buffer.push(sample);
+            }
+        }
+        assert_eq!(buffer.len(), 30.min(4*samples.len()));
+        assert_eq!(buffer.sample(8).len(), 8);
+        let newest = buffer.samples.back().unwrap().board.clone();
+        buffer.set_capacity(5);
+        assert_eq!(buffer.len(), 5);
+        assert_eq!(buffer.samples.back().unwrap().board, newest);
+        buffer.push(samples[0].clone());
+        assert_eq!(buffer.len(), 5);
+        let noise = dirichlet(0.3, 7);
+        assert!((noise.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+}
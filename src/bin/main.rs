@@ -5,15 +5,24 @@ extern crate serde_json;
 extern crate gamesolver;
 extern crate signal_hook;
 
-use gamesolver::games::connect4::{Connect4};
+use gamesolver::games::connect4::{threats, Connect4};
 use gamesolver::games::stack4::Stack4;
-use gamesolver::games::{GameState, Player};
-use gamesolver::evaluators::{Evaluator, Connect4Evaluators, Stack4Evaluators, simple::SimpleEval, cnn::CNNEval};
+use gamesolver::games::mnk::{TicTacToe, FiveInARow, Gomoku};
+use gamesolver::games::othello::Othello;
+use gamesolver::games::{GameState, Player, PlayError};
+use gamesolver::games::notation::{self, ActionNotation};
+use gamesolver::evaluators::{Evaluator, Connect4Evaluators, Stack4Evaluators, GenericEvaluators, simple::SimpleEval, cnn::CNNEval};
 use gamesolver::agents::{Agent, MinimaxPolicyAgent, MinimaxAgent};
 use gamesolver::matchmaker::{MatchMaker, PlayableGame, user_vs_agent};
+use gamesolver::mcts::{MctsAgent, MctsBudget, Rollout};
+use gamesolver::alphazero::{AlphaZero, AlphaZeroConfig, PolicyValueNet, ReplayBuffer};
 use gamesolver::games::{Game};
 use gamesolver::qlearning::{QLearning, RL};
 use gamesolver::policies::{EpsilonGreedy};
+use gamesolver::solver::{Solver, Outcome};
+use gamesolver::book::{OpeningBook, BookAgent};
+use gamesolver::endgame::EndgameDb;
+use gamesolver::record::read_records;
 use clap::{Parser, Subcommand, ArgEnum};
 use serde::{Serialize};
 use serde::de::DeserializeOwned;
@@ -38,7 +47,13 @@ struct Cli {
 #[derive(ArgEnum, Clone)]
 enum Games {
     Connect4,
-    Stack4
+    Stack4,
+    TicTacToe,
+    /// Five in a row on a 9x9 board.
+    FiveInARow,
+    /// Free-style Gomoku on a 15x15 board.
+    Gomoku,
+    Othello,
 }
 
 #[derive(Subcommand)]
@@ -81,7 +96,10 @@ enum Commands {
     },
     /// Lets user play a game against the AI.
     Play {
-        ai_file: String
+        ai_file: String,
+        /// Opening book made with build-book.
+        #[clap(long)]
+        book: Option<String>,
     },
     Compare {
         ai_file1: String,
@@ -90,6 +108,80 @@ enum Commands {
         nb_games: u32,
         #[clap(default_value_t=4)]
         depth: u32,
+        /// Saves every game played to this file.
+        #[clap(long)]
+        records: Option<String>,
+    },
+    /// Plays the AI searching with minimax against Monte Carlo tree search with random rollouts.
+    CompareMcts {
+        ai_file: String,
+        #[clap(default_value_t=100)]
+        nb_games: u32,
+        #[clap(default_value_t=4)]
+        depth: u32,
+        /// Number of MCTS iterations per move.
+        #[clap(short, long, default_value_t=1000)]
+        iterations: u32,
+        /// Saves every game played to this file.
+        #[clap(long)]
+        records: Option<String>,
+    },
+    /// Trains a policy+value model, created with models/alphazero.py, from self-play with PUCT.
+    AlphaZero {
+        model_file: String,
+        #[clap(short, long, default_value_t=20)]
+        iterations: u32,
+        /// Self-play games per iteration.
+        #[clap(short, long, default_value_t=10)]
+        games: u32,
+        /// MCTS simulations per move.
+        #[clap(short, long, default_value_t=100)]
+        simulations: u32,
+        /// Gradient steps per iteration.
+        #[clap(long, default_value_t=50)]
+        train_steps: u32,
+        /// File where the replay buffer is kept between runs.
+        #[clap(long)]
+        buffer_file: Option<String>,
+        #[clap(short, long)]
+        /// Print the iteration and the loss.
+        progress: bool,
+    },
+    /// Builds an opening book by searching every position within the first plies
+    /// with the AI, adding to the book if it already exists.
+    BuildBook {
+        ai_file: String,
+        book_file: String,
+        #[clap(short, long, default_value_t=4)]
+        plies: u32,
+        #[clap(short, long, default_value_t=6)]
+        depth: u32,
+        /// Game records, the moves with the best results in them are added before searching.
+        #[clap(long)]
+        records: Option<String>,
+    },
+    /// Solves Stack4 positions with few empty cells reached in random games and saves
+    /// them to the endgame database, only available for stack4.
+    BuildEndgame {
+        db_file: String,
+        #[clap(short, long, default_value_t=10)]
+        max_empty: u32,
+        #[clap(short, long, default_value_t=100)]
+        games: u32,
+    },
+    /// Computes the game theoretic value of a position, only available for connect4.
+    Solve {
+        /// The columns (1-7) played from the empty board, e.g. 4453, or the position
+        /// in the FEN-like form, e.g. "7/7/7/7/3y3/2yrr2 r".
+        #[clap(default_value="")]
+        position: String,
+    },
+    /// Lists the threats of both players and who controls zugzwang by the odd/even threat rules,
+    /// only available for connect4.
+    Threats {
+        /// The position in the same forms as for solve.
+        #[clap(default_value="")]
+        position: String,
     }
 }
 
@@ -186,9 +278,9 @@ impl Commands {
         let serialized_ai = serde_json::to_string(&ai).unwrap();
         std::fs::write(ai_file, &serialized_ai).unwrap();
     }
-    fn compare<G, E>(ai_file1: String, ai_file2: String, nb_games: u32, depth: u32)
+    fn compare<G, E>(ai_file1: String, ai_file2: String, nb_games: u32, depth: u32, records: Option<String>)
         where
-            G: Game,
+            G: ActionNotation,
             E: Evaluator<G>+Serialize+DeserializeOwned,
     {
         let ai1: QLearning<E> = serde_json::from_str(&std::fs::read_to_string(&ai_file1).expect("valid file")).expect("json of RL");
@@ -198,14 +290,132 @@ impl Commands {
         let mut mm = MatchMaker::new();
         mm.add_agent(&agenta);
         mm.add_agent(&agentb);
+        mm.evaluations = records.is_some();
         mm.play_n_games(nb_games);
         println!("{:?}", mm.scores());
+        if let Some(records) = records {
+            mm.save_records(&records).expect("writable records file");
+        }
+    }
+    fn compare_mcts<G, E>(ai_file: String, nb_games: u32, depth: u32, iterations: u32, records: Option<String>)
+        where
+            G: ActionNotation,
+            E: Evaluator<G>+Serialize+DeserializeOwned,
+    {
+        let ai: QLearning<E> = serde_json::from_str(&std::fs::read_to_string(&ai_file).expect("valid file")).expect("json of RL");
+        let agenta = MinimaxPolicyAgent::new(ai.get_evaluator(), ai.get_policy(), depth);
+        let agentb = MctsAgent::new(ai.get_evaluator(), MctsBudget::Iterations(iterations), Rollout::Random);
+        let mut mm = MatchMaker::new();
+        mm.add_agent(&agenta);
+        mm.add_agent(&agentb);
+        mm.evaluations = records.is_some();
+        mm.play_n_games(nb_games);
+        println!("{:?}", mm.scores());
+        if let Some(records) = records {
+            mm.save_records(&records).expect("writable records file");
+        }
+    }
+    fn alpha_zero<G: Game>(model_file: String, iterations: u32, games: u32, simulations: u32, train_steps: u32, buffer_file: Option<String>, progress: bool) {
+        let config = AlphaZeroConfig { simulations, ..Default::default() };
+        let net = PolicyValueNet::new(&model_file).expect("valid model file");
+        let mut trainer = AlphaZero::new(net, config).unwrap();
+        if let Some(Ok(content)) = buffer_file.as_ref().map(std::fs::read_to_string) {
+            let buffer: ReplayBuffer = serde_json::from_str(&content).expect("json of replay buffer");
+            trainer.buffer = buffer;
+            trainer.buffer.set_capacity(config.buffer_size);
+        }
+        let term = Arc::new(AtomicBool::new(false));
+        let err = signal_hook::flag::register(signal_hook::consts::SIGQUIT, Arc::clone(&term));
+        for i in 0..iterations {
+            if term.load(Ordering::Relaxed) && err.is_ok() {
+                break;
+            }
+            trainer.self_play::<G>(games);
+            let mut loss = 0.0;
+            for _ in 0..train_steps {
+                loss += trainer.train_step::<G>();
+            }
+            if progress {
+                println!("iteration: {} samples: {} loss: {:.4}", i, trainer.buffer.len(), loss/train_steps as f64);
+            }
+        }
+        trainer.net.save(&model_file).unwrap();
+        if let Some(buffer_file) = buffer_file {
+            std::fs::write(buffer_file, serde_json::to_string(&trainer.buffer).unwrap()).unwrap();
+        }
+    }
+    fn build_book<G, E>(ai_file: String, book_file: String, plies: u32, depth: u32, records: Option<String>)
+        where
+            G: ActionNotation,
+            E: Evaluator<G>+Serialize+DeserializeOwned,
+    {
+        let ai: QLearning<E> = serde_json::from_str(&std::fs::read_to_string(&ai_file).expect("valid file")).expect("json of RL");
+        let agent = MinimaxAgent::new(ai.get_evaluator(), depth);
+        let mut book = OpeningBook::load(&book_file).unwrap_or_default();
+        if let Some(records) = records {
+            let records = read_records::<G>(&std::fs::read_to_string(&records).expect("valid file")).expect("game records");
+            book.add_records(&records, plies);
+        }
+        book.build(&agent, plies);
+        book.save(&book_file).expect("writable book file");
+        println!("{} positions in the book", book.len());
+    }
+    fn build_endgame(db_file: String, max_empty: u32, games: u32) {
+        let mut db = EndgameDb::load(&db_file).unwrap_or_else(|_| EndgameDb::new(max_empty));
+        if db.max_empty != max_empty {
+            println!("the database has max_empty {}", db.max_empty);
+            return;
+        }
+        let start = std::time::Instant::now();
+        db.generate(games);
+        db.save(&db_file).expect("writable database file");
+        println!("{} positions in {:.2?}", db.len(), start.elapsed());
+    }
+    fn solve(position: String) {
+        let board: Connect4 = match notation::parse_position(&position) {
+            Ok(board) => board,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        println!("{:?}", board);
+        println!("{}", board);
+        if board.game_state() != GameState::InProgress {
+            println!("{}", PlayError::GameOver);
+            return;
+        }
+        let mut solver = Solver::new();
+        let start = std::time::Instant::now();
+        println!("{:?} to move: {:?}", board.cur_player(), solver.solve(&board));
+        for (action, score) in solver.action_scores(&board) {
+            let outcome = Outcome::from_score(score, &board);
+            println!("{}: score {:>3} {:?}", Connect4::format_action(action), score, outcome);
+        }
+        println!("{} nodes in {:.2?}", solver.node_count, start.elapsed());
+    }
+    fn threats(position: String) {
+        let board: Connect4 = match notation::parse_position(&position) {
+            Ok(board) => board,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        println!("{:?}", board);
+        let analysis = threats::analyse(&board);
+        for threat in &analysis.threats {
+            let parity = if threat.odd() {"odd"} else {"even"};
+            let playable = if threat.playable {", playable"} else {""};
+            println!("{:?}: column {} row {} ({}{})", threat.player, threat.x+1, threat.y+1, parity, playable);
+        }
+        println!("{:?} controls zugzwang, winner by the threat rules: {:?}", analysis.zugzwang, analysis.winner);
     }
 }
 
 fn run_command<G, E>(command: Commands) 
     where
-        G: PlayableGame,
+        G: PlayableGame+ActionNotation,
         E: Evaluator<G>+Serialize+DeserializeOwned
 {
     match command {
@@ -218,15 +428,34 @@ fn run_command<G, E>(command: Commands)
         Commands::TrainAgainst { ai_file, opponent_file, iterations, progress, scores} => {
             Commands::train_against::<G, E>(ai_file, opponent_file, iterations, progress, scores);
         }
-        Commands::Play {ai_file} => {
+        Commands::Play {ai_file, book} => {
             let ai: QLearning<E> = serde_json::from_str(&std::fs::read_to_string(&ai_file).expect("valid file")).expect("json of RL");
             let mut agenta = MinimaxPolicyAgent::new(ai.get_evaluator(), ai.get_policy(), 3);
             agenta.batch_depth = 2;
-            user_vs_agent(&agenta);
+            let book = book.map_or_else(OpeningBook::new, |book| OpeningBook::load(&book).expect("valid opening book"));
+            user_vs_agent(&BookAgent::new(&book, &agenta));
+        }
+        Commands::BuildBook {ai_file, book_file, plies, depth, records} => {
+            Commands::build_book::<G, E>(ai_file, book_file, plies, depth, records);
         }
-        Commands::Compare {ai_file1, ai_file2, nb_games, depth} => {
-            Commands::compare::<G, E>(ai_file1, ai_file2, nb_games, depth);
+        Commands::Compare {ai_file1, ai_file2, nb_games, depth, records} => {
+            Commands::compare::<G, E>(ai_file1, ai_file2, nb_games, depth, records);
         }   
+        Commands::CompareMcts {ai_file, nb_games, depth, iterations, records} => {
+            Commands::compare_mcts::<G, E>(ai_file, nb_games, depth, iterations, records);
+        }
+        Commands::AlphaZero {model_file, iterations, games, simulations, train_steps, buffer_file, progress} => {
+            Commands::alpha_zero::<G>(model_file, iterations, games, simulations, train_steps, buffer_file, progress);
+        }
+        Commands::Solve {..} => {
+            println!("solve is only available for connect4");
+        }
+        Commands::Threats {..} => {
+            println!("threats is only available for connect4");
+        }
+        Commands::BuildEndgame {..} => {
+            println!("build-endgame is only available for stack4");
+        }
     }
 }
 
@@ -243,27 +472,40 @@ fn main() {
     //user_vs_agent::<Stack4, _>(&agent);
     return ();
     */
-    let ai: QLearning<Stack4Evaluators> = serde_json::from_str(&std::fs::read_to_string("smallcnn.json").expect("valid file")).expect("json of RL");
-
-    println!("{:?}", _mse_stack4(ai.get_evaluator()));
-
     let args = Cli::parse();
-    match args.game {
-        Games::Connect4 => {
-            run_command::<Connect4, Connect4Evaluators>(args.command);
+    match (args.game, args.command) {
+        (Games::Connect4, Commands::Solve {position}) => {
+            Commands::solve(position);
         },
-        Games::Stack4 => {
-            run_command::<Stack4, Stack4Evaluators>(args.command);
+        (Games::Connect4, Commands::Threats {position}) => {
+            Commands::threats(position);
+        },
+        (Games::Connect4, command) => {
+            run_command::<Connect4, Connect4Evaluators>(command);
+        },
+        (Games::Stack4, Commands::BuildEndgame {db_file, max_empty, games}) => {
+            Commands::build_endgame(db_file, max_empty, games);
+        },
+        (Games::Stack4, command) => {
+            run_command::<Stack4, Stack4Evaluators>(command);
+        }
+        (Games::TicTacToe, command) => {
+            run_command::<TicTacToe, GenericEvaluators>(command);
+        }
+        (Games::FiveInARow, command) => {
+            run_command::<FiveInARow, GenericEvaluators>(command);
+        }
+        (Games::Gomoku, command) => {
+            run_command::<Gomoku, GenericEvaluators>(command);
+        }
+        (Games::Othello, command) => {
+            run_command::<Othello, GenericEvaluators>(command);
         }
     }
 }
 
 fn _mse_stack4<E: Evaluator<Stack4>>(evaluator: &E) -> f64 {
-    let actions = vec![(3, 0), (4, 0), (3, 1), (2, 0), (2, 1), (5, 0), (0, 3), (2, 2), (0, 4), (2, 3), (3, 2), (6, 0)];
-    let mut board = Stack4::new();
-    for action in actions {
-        board.play_action(action);
-    }
+    let board: Stack4 = notation::replay("3,0 4,0 3,1 2,0 2,1 5,0 0,3 2,2 0,4 2,3 3,2 6,0").unwrap();
     let vyellow = evaluator.value(&board, Player::Yellow);
     let vred = evaluator.value(&board, Player::Red);
     println!("vyellow={:.5}", vyellow);
@@ -276,11 +518,7 @@ fn _mse_stack4<E: Evaluator<Stack4>>(evaluator: &E) -> f64 {
 
 fn _mse_cnneval<E: Evaluator<Connect4>>(evaluator: &E) -> f64 {
     // good for yellow
-    let actions = vec![4, 2, 3, 5, 5, 3, 5, 5, 6, 5, 6, 2, 6, 6, 6, 3, 6, 4];
-    let mut board = Connect4::new();
-    for action in actions {
-        board.play_action(action);
-    }
+    let board: Connect4 = notation::replay("534664667673777475").unwrap();
     let vyellow = evaluator.value(&board, Player::Yellow);
     let vred = evaluator.value(&board, Player::Red);
     println!("vyellow={:.5}", vyellow);
@@ -8,26 +8,37 @@ extern crate futures;
 
 use num_traits::{FromPrimitive};
 use lazy_static::lazy_static;
-use actix_web::{get, web, App, HttpServer, Responder};
+use actix_web::{get, web, error, App, HttpServer, Responder};
 use actix_web::middleware::Logger;
 use actix_files::Files;
 
 use gamesolver::evaluators::CNNEval;
 use gamesolver::agents::{CompositeAgent, Agent};
-use gamesolver::evaluators::Stack4Evaluators;
+use gamesolver::book::{OpeningBook, BookAgent};
+use gamesolver::endgame::{EndgameDb, ProbingEval};
+use gamesolver::evaluators::{Stack4Evaluators, SimpleEval};
 use gamesolver::qlearning::{QLearning};
-use gamesolver::games::Player;
+use gamesolver::search::SearchResult;
+use gamesolver::games::{Player, PlayError};
+use gamesolver::games::notation::{self, NotationError};
 use gamesolver::games::stack4::Stack4;
-use gamesolver::games::Game;
+use gamesolver::games::{Game, GameState};
 use serde::{Serialize, Deserialize};
 use std::fs;
 
 static AI_PATH: &str = "new_cons.json";
+static BOOK_PATH: &str = "stack4_book.bin";
+static ENDGAME_PATH: &str = "stack4_endgame.bin";
 
 #[derive(Serialize, Deserialize)]
 struct MoveRequest {
+    #[serde(default)]
     board: Vec<u8>,
+    #[serde(default)]
     player_to_move: u8,
+    // the position in notation, used instead of the board when given.
+    #[serde(default)]
+    position: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -35,6 +46,9 @@ struct Move {
     x: usize,
     y: usize,
     player: u8,
+    score: f64, // serialized as null when the position is decided.
+    depth: u32,
+    pv: Vec<(usize, usize)>,
 }
 
 lazy_static! {
@@ -42,12 +56,16 @@ lazy_static! {
         let ai: QLearning<Stack4Evaluators> = serde_json::from_str(&fs::read_to_string(AI_PATH).unwrap()).unwrap();
         ai.evaluator
     };
+    // the server also works without a book.
+    static ref BOOK: OpeningBook = OpeningBook::load(BOOK_PATH).unwrap_or_default();
+    static ref ENDGAME: EndgameDb = EndgameDb::load(ENDGAME_PATH).unwrap_or_else(|_| EndgameDb::new(0));
 }
 
-fn calc_move(board: &Stack4, player: Player) -> <Stack4 as Game>::Action {
+fn calc_move(board: &Stack4, player: Player) -> SearchResult<<Stack4 as Game>::Action> {
     //let agent = MinimaxAgent::<Stack4Evaluators>::new(&EVALUATOR, 5);
-    let agent = CompositeAgent::<Stack4Evaluators>::new(&EVALUATOR, 4, 0, 6);
-    agent.get_action(board, player)
+    let agent = CompositeAgent::<Stack4Evaluators>::new(&EVALUATOR, 4, 0, 6)
+        .with_simple_eval(ProbingEval::new(SimpleEval::new(), &ENDGAME));
+    BookAgent::new(&BOOK, &agent).analyse(board, player).unwrap()
 }
 
 #[get("/{name}/index.html")]
@@ -56,30 +74,46 @@ async fn index(path: web::Path<String>) -> impl Responder {
     format!("Hello {}!", name)
 }
 
-async fn request_move(info: web::Json<MoveRequest>) -> web::Json<Move> {
-    println!("post!");
-
-    let mut board = Stack4::new();
-    for (i, &player) in info.board.iter().enumerate() {
-        let row = i%8;
-        let col = i/8;
-        if player == 0 {
-            board.set(col, row, player);
-        } else {
-            board.set(col, row, player);
+// Builds the position sent by the client, the board is given column by column.
+fn board_from_request(info: &MoveRequest) -> actix_web::Result<Stack4> {
+    let board = match &info.position {
+        Some(position) => notation::parse_position(position),
+        None => {
+            FromPrimitive::from_u8(info.player_to_move)
+                .ok_or_else(|| NotationError::InvalidPosition(format!("invalid player {}", info.player_to_move)))
+                .and_then(|player| Stack4::from_cells(&info.board, player))
         }
+    }.map_err(error::ErrorBadRequest)?;
+    if board.game_state() != GameState::InProgress {
+        return Err(error::ErrorBadRequest(PlayError::GameOver));
     }
-    let player = FromPrimitive::from_u8(info.player_to_move).expect("an u8");
-    let (x, y) = calc_move(&board, player);
+    Ok(board)
+}
+
+async fn request_move(info: web::Json<MoveRequest>) -> actix_web::Result<web::Json<Move>> {
+    println!("post!");
+
+    let mut board = board_from_request(&info)?;
+    let player = board.cur_player();
+    let result = calc_move(&board, player);
+    let (x, y) = result.best_action;
     println!("{:?}", board);
-    println!("{:?}", (x,y));
-    web::Json(
+    println!("{:?} score: {} pv: {:?}", (x,y), result.score, result.pv);
+    println!("{}", result.stats);
+    board.try_play_action(result.best_action).map_err(error::ErrorInternalServerError)?;
+    if board.game_state() != GameState::InProgress {
+        println!("{:?}", board.game_state());
+    }
+    Ok(web::Json(
         Move {
             x,
             y,
-            player: info.player_to_move
+            player: player as u8,
+            score: result.score,
+            depth: result.depth,
+            pv: result.pv,
         }
-    )
+    ))
 }
 
 #[actix_web::main]
@@ -0,0 +1,334 @@
+use crate::agents::Agent;
+use crate::games::{Game, GameState, Player};
+use crate::record::GameRecord;
+use crate::search::{SearchResult, SearchStats};
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+// The move to play in a position, stored as the canonical uid of the position it leads to
+// so that the same entry works for every symmetry of the position.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BookEntry {
+    pub next: u128,
+    pub score: f32, // for the player to move.
+    pub kind: ScoreKind,
+}
+
+// What the score of an entry is.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScoreKind {
+    // the score the agent that built the book gave the move.
+    Search,
+    // the average result between -1 and 1 of the recorded games where the move was played.
+    Results,
+}
+
+// Moves for the positions early in the game, keyed by canonical_uid.
+//
+// On disk a book is the magic bytes "BOK2" followed by the entries sorted by key, each entry is
+// the key, next and score in little endian and a byte that is 0 for ScoreKind::Search and 1 for
+// ScoreKind::Results, 37 bytes in total. Books with the magic bytes "BOOK" have no kinds and
+// 36 byte entries, they are read as search scores.
+#[derive(Clone, Default, Debug)]
+pub struct OpeningBook {
+    entries: HashMap<u128, BookEntry>,
+}
+
+const MAGIC: &[u8; 4] = b"BOK2";
+const ENTRY_SIZE: usize = 37;
+const OLD_MAGIC: &[u8; 4] = b"BOOK";
+const OLD_ENTRY_SIZE: usize = 36;
+
+impl OpeningBook {
+    pub fn new() -> Self {
+        OpeningBook { entries: HashMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn insert<G: Game>(&mut self, board: &G, action: G::Action, score: f32, kind: ScoreKind) {
+        let mut next = *board;
+        next.play_action(action);
+        self.entries.insert(board.canonical_uid(), BookEntry { next: next.canonical_uid(), score, kind });
+    }
+
+    pub fn get<G: Game>(&self, board: &G) -> Option<BookEntry> {
+        self.entries.get(&board.canonical_uid()).copied()
+    }
+
+    // The book move and its entry, None if the position is not in the book.
+    pub fn action<G: Game>(&self, board: &G) -> Option<(G::Action, BookEntry)> {
+        let entry = self.get(board)?;
+        let mut board = *board;
+        let actions: Vec<G::Action> = board.legal_actions().collect();
+        actions.into_iter().find(|&action| {
+            let undo = board.play_action(action);
+            let found = board.canonical_uid() == entry.next;
+            board.reverse_last_action(undo);
+            found
+        }).map(|action| (action, entry))
+    }
+
+    // Adds the move chosen by 'agent' in every position reachable within 'plies' moves
+    // that is not already in the book.
+    pub fn build<G: Game>(&mut self, agent: &dyn Agent<G>, plies: u32) {
+        let mut visited = HashSet::new();
+        let mut stack = vec![G::new()];
+        while let Some(board) = stack.pop() {
+            if board.game_state() != GameState::InProgress || board.length() >= plies || !visited.insert(board.canonical_uid()) {
+                continue;
+            }
+            if self.get(&board).is_none() {
+                let (action, score) = match agent.analyse(&board, board.cur_player()) {
+                    Some(result) => (result.best_action, result.score as f32),
+                    None => (agent.get_action(&board, board.cur_player()), 0.0),
+                };
+                self.insert(&board, action, score, ScoreKind::Search);
+            }
+            for action in board.legal_actions() {
+                let mut next = board;
+                next.play_action(action);
+                stack.push(next);
+            }
+        }
+    }
+
+    // Adds the move with the best average result among the finished games in 'records', for
+    // the positions within the first 'plies' moves. The score is the average result, see ScoreKind::Results.
+    pub fn add_records<G: Game>(&mut self, records: &[GameRecord<G>], plies: u32) {
+        // (action, sum of results for the player to move, number of games) by the position the action leads to.
+        type Moves<A> = HashMap<u128, (A, f64, u32)>;
+        let mut results: HashMap<u128, (G, Moves<G::Action>)> = HashMap::new();
+        for record in records.iter().filter(|r| r.result != GameState::InProgress) {
+            let mut board = G::new();
+            for &action in record.actions.iter().take(plies as usize) {
+                let result = match record.result {
+                    GameState::Won(p) if p == board.cur_player() => 1.0,
+                    GameState::Won(_) => -1.0,
+                    _ => 0.0,
+                };
+                let mut next = board;
+                next.play_action(action);
+                let moves = &mut results.entry(board.canonical_uid()).or_insert_with(|| (board, HashMap::new())).1;
+                let entry = moves.entry(next.canonical_uid()).or_insert((action, 0.0, 0));
+                entry.1 += result;
+                entry.2 += 1;
+                board = next;
+            }
+        }
+        for (board, moves) in results.values() {
+            let average = |(_, sum, n): &(G::Action, f64, u32)| sum / *n as f64;
+            let best = moves.values().max_by(|a, b| average(a).partial_cmp(&average(b)).unwrap()).unwrap();
+            self.insert(board, best.0, average(best) as f32, ScoreKind::Results);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut keys: Vec<&u128> = self.entries.keys().collect();
+        keys.sort();
+        let mut bytes = Vec::with_capacity(MAGIC.len()+ENTRY_SIZE*keys.len());
+        bytes.extend_from_slice(MAGIC);
+        for key in keys {
+            let entry = &self.entries[key];
+            bytes.extend_from_slice(&key.to_le_bytes());
+            bytes.extend_from_slice(&entry.next.to_le_bytes());
+            bytes.extend_from_slice(&entry.score.to_le_bytes());
+            bytes.push(match entry.kind {
+                ScoreKind::Search => 0,
+                ScoreKind::Results => 1,
+            });
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let (entries, entry_size) = if let Some(entries) = bytes.strip_prefix(MAGIC) {
+            (entries, ENTRY_SIZE)
+        } else if let Some(entries) = bytes.strip_prefix(OLD_MAGIC) {
+            (entries, OLD_ENTRY_SIZE)
+        } else {
+            return Err(invalid("not an opening book"));
+        };
+        if entries.len() % entry_size != 0 {
+            return Err(invalid("truncated opening book"));
+        }
+        let mut book = OpeningBook::new();
+        for entry in entries.chunks(entry_size) {
+            let key = u128::from_le_bytes(entry[0..16].try_into().unwrap());
+            let next = u128::from_le_bytes(entry[16..32].try_into().unwrap());
+            let score = f32::from_le_bytes(entry[32..36].try_into().unwrap());
+            let kind = match entry.get(36) {
+                None | Some(0) => ScoreKind::Search,
+                Some(1) => ScoreKind::Results,
+                Some(_) => return Err(invalid("unknown kind of score")),
+            };
+            book.entries.insert(key, BookEntry { next, score, kind });
+        }
+        Ok(book)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        OpeningBook::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+// Plays the book move when the position is in the book and otherwise lets 'inner' decide.
+pub struct BookAgent<'a, G> {
+    book: &'a OpeningBook,
+    inner: &'a dyn Agent<G>,
+}
+
+impl<'a, G> BookAgent<'a, G> {
+    pub fn new(book: &'a OpeningBook, inner: &'a dyn Agent<G>) -> Self {
+        BookAgent {
+            book,
+            inner,
+        }
+    }
+}
+
+impl<'a, G: Game> Agent<G> for BookAgent<'a, G> {
+    fn get_action(&self, board: &G, player: Player) -> G::Action {
+        match self.book.action(board) {
+            Some((action, _)) => action,
+            None => self.inner.get_action(board, player),
+        }
+    }
+
+    fn get_action_explored(&self, board: &G, player: Player) -> (G::Action, bool) {
+        match self.book.action(board) {
+            Some((action, _)) => (action, false),
+            None => self.inner.get_action_explored(board, player),
+        }
+    }
+
+    // book moves with a search score are reported as a search of depth 0. The average result of
+    // games is no score of a search, so the position after such a book move is analysed by 'inner'.
+    fn analyse(&self, board: &G, player: Player) -> Option<SearchResult<G::Action>> {
+        match self.book.action(board) {
+            Some((action, BookEntry { score, kind: ScoreKind::Search, .. })) => Some(SearchResult {
+                best_action: action,
+                score: score as f64,
+                pv: vec![action],
+                depth: 0,
+                root_scores: vec![(action, score as f64)],
+                stats: SearchStats::default(),
+            }),
+            Some((action, _)) => {
+                let mut next = *board;
+                next.play_action(action);
+                let (score, pv, depth, stats) = match next.game_state() {
+                    GameState::Won(p) => (if p == player {1./0.} else {-1./0.}, vec![action], 0, SearchStats::default()),
+                    GameState::Draw => (0.0, vec![action], 0, SearchStats::default()),
+                    GameState::InProgress => {
+                        let result = self.inner.analyse(&next, !player)?;
+                        (-result.score, [vec![action], result.pv].concat(), result.depth+1, result.stats)
+                    },
+                };
+                Some(SearchResult {
+                    best_action: action,
+                    score,
+                    pv,
+                    depth,
+                    root_scores: vec![(action, score)],
+                    stats,
+                })
+            },
+            None => self.inner.analyse(board, player),
+        }
+    }
+
+    fn description(&self) -> String {
+        format!("book with {} positions, {}", self.book.len(), self.inner.description())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::agents::MinimaxAgent;
+    use crate::evaluators::SimpleEval;
+    use crate::games::connect4::Connect4;
+    use crate::games::stack4::Stack4;
+    use crate::matchmaker::play_recorded_game;
+
+    #[test]
+    fn symmetric_positions() {
+        let evaluator = SimpleEval::new();
+        let agent = MinimaxAgent::new(&evaluator, 3);
+        let mut book = OpeningBook::new();
+        book.build::<Stack4>(&agent, 3);
+        // the starting position, 4 positions after one move up to symmetry and at most 28 replies to each.
+        assert!(book.len() > 1+4 && book.len() <= 1+4+4*28);
+
+        let book = OpeningBook::from_bytes(&book.to_bytes()).unwrap();
+        let mut board = Stack4::new();
+        board.play_action((0, 0));
+        let mut mirrored = Stack4::new();
+        mirrored.play_action((7, 0));
+        let (action, _) = book.action(&board).unwrap();
+        let (mirrored_action, _) = book.action(&mirrored).unwrap();
+        board.play_action(action);
+        mirrored.play_action(mirrored_action);
+        assert_eq!(board.canonical_uid(), mirrored.canonical_uid());
+        let reply = board.legal_actions().next().unwrap();
+        board.play_action(reply);
+        assert!(book.action(&board).is_none());
+        assert!(OpeningBook::from_bytes(b"BOOK1").is_err());
+        // books from before the kinds of scores are read as search scores.
+        let mut old = b"BOOK".to_vec();
+        old.extend_from_slice(&book.to_bytes()[4..4+ENTRY_SIZE-1]);
+        let old = OpeningBook::from_bytes(&old).unwrap();
+        assert_eq!(old.len(), 1);
+        assert_eq!(old.entries.values().next().unwrap().kind, ScoreKind::Search);
+    }
+
+    #[test]
+    fn book_agent() {
+        let evaluator = SimpleEval::new();
+        let inner = MinimaxAgent::new(&evaluator, 2);
+        let mut book = OpeningBook::new();
+        let mut board = Connect4::new();
+        book.insert(&board, 0, 0.5, ScoreKind::Search);
+        let agent = BookAgent::new(&book, &inner);
+        // the starting position is symmetric so both edge columns lead to the same position.
+        assert!([0, 6].contains(&agent.get_action(&board, Player::Red)));
+        // the mirrored position uses the mirrored move.
+        board.play_action(6);
+        book.insert(&board.symmetry(), 6, 0.0, ScoreKind::Search);
+        let agent = BookAgent::new(&book, &inner);
+        assert_eq!(agent.analyse(&board, Player::Yellow).unwrap().best_action, 0);
+
+        let mut book = OpeningBook::new();
+        let records: Vec<_> = (0..4).map(|_| play_recorded_game::<Connect4>(&inner, &inner, false)).collect();
+        book.add_records(&records, 2);
+        assert!(book.get(&Connect4::new()).is_some());
+        let agent = BookAgent::new(&book, &inner);
+        let played = |action| {
+            let mut board = Connect4::new();
+            board.play_action(action);
+            board.canonical_uid()
+        };
+        assert!(records.iter().any(|r| played(r.actions[0]) == played(agent.get_action(&Connect4::new(), Player::Red))));
+        // the scores from games are kept apart from search scores, the analysis searches after the book move.
+        assert_eq!(book.get(&Connect4::new()).unwrap().kind, ScoreKind::Results);
+        let result = agent.analyse(&Connect4::new(), Player::Red).unwrap();
+        assert_eq!(played(result.best_action), played(agent.get_action(&Connect4::new(), Player::Red)));
+        // the book move and the two plies searched by 'inner'.
+        assert_eq!(result.depth, 3);
+        assert!(result.stats.nodes > 0);
+        let copy = OpeningBook::from_bytes(&book.to_bytes()).unwrap();
+        assert_eq!(copy.get(&Connect4::new()), book.get(&Connect4::new()));
+    }
+}
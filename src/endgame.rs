@@ -0,0 +1,268 @@
+use crate::evaluators::Evaluator;
+use crate::games::{Game, GameState, Player};
+use crate::games::stack4::Stack4;
+use std::collections::HashMap;
+use std::io;
+
+// Exact values of Stack4 positions with at most 'max_empty' empty cells, keyed by canonical_uid.
+// The values are for the player to move, 1 for a win, 0 for a draw and -1 for a loss.
+//
+// Positions are solved when they are first seen and every position visited while solving is
+// kept as well. On disk the database is the magic bytes "EGDB" and max_empty followed by the
+// entries sorted by key, each entry is the key in little endian and the value, 17 bytes in total.
+#[derive(Clone, Debug)]
+pub struct EndgameDb {
+    entries: HashMap<u128, i8>,
+    pub max_empty: u32,
+}
+
+const MAGIC: &[u8; 4] = b"EGDB";
+const ENTRY_SIZE: usize = 17;
+const NB_CELLS: u32 = 64;
+
+impl EndgameDb {
+    pub fn new(max_empty: u32) -> Self {
+        EndgameDb {
+            entries: HashMap::new(),
+            max_empty,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn empty_cells(board: &Stack4) -> u32 {
+        NB_CELLS-board.nb_moves
+    }
+
+    // The value of a position that has been solved.
+    pub fn value(&self, board: &Stack4) -> Option<i8> {
+        if Self::empty_cells(board) > self.max_empty {
+            return None;
+        }
+        self.entries.get(&board.canonical_uid()).copied()
+    }
+
+    // Solves 'board' if it has few enough empty cells and returns its value.
+    pub fn solve(&mut self, board: &Stack4) -> Option<i8> {
+        if Self::empty_cells(board) > self.max_empty {
+            return None;
+        }
+        Some(self.solve_exact(&mut board.clone()))
+    }
+
+    fn solve_exact(&mut self, board: &mut Stack4) -> i8 {
+        match board.game_state() {
+            GameState::Won(p) => return if p == board.cur_player() {1} else {-1},
+            GameState::Draw => return 0,
+            GameState::InProgress => {},
+        }
+        let key = board.canonical_uid();
+        if let Some(&value) = self.entries.get(&key) {
+            return value;
+        }
+        let mut value = -1;
+        // winning actions come first so most wins are found after one action.
+        let actions: Vec<_> = board.legal_actions().collect();
+        for action in actions {
+            let undo = board.play_action(action);
+            value = value.max(-self.solve_exact(board));
+            board.reverse_last_action(undo);
+            if value == 1 {
+                break;
+            }
+        }
+        self.entries.insert(key, value);
+        value
+    }
+
+    // Plays 'games' random games, avoiding winning moves so that the games reach the endgame,
+    // and solves the first position of each game with few enough empty cells.
+    pub fn generate(&mut self, games: u32) {
+        for _ in 0..games {
+            let mut board = Stack4::new();
+            while board.game_state() == GameState::InProgress {
+                if self.solve(&board).is_some() {
+                    break;
+                }
+                let actions: Vec<_> = board.legal_actions().collect();
+                let quiet: Vec<_> = actions.iter().copied().filter(|&a| !board.is_winning_action(a, board.cur_player())).collect();
+                let actions = if quiet.is_empty() {actions} else {quiet};
+                board.play_action(actions[fastrand::usize(0..actions.len())]);
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut keys: Vec<&u128> = self.entries.keys().collect();
+        keys.sort();
+        let mut bytes = Vec::with_capacity(MAGIC.len()+1+ENTRY_SIZE*keys.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(self.max_empty as u8);
+        for key in keys {
+            bytes.extend_from_slice(&key.to_le_bytes());
+            bytes.push(self.entries[key] as u8);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let (&max_empty, entries) = bytes.strip_prefix(MAGIC).and_then(|b| b.split_first())
+            .ok_or_else(|| invalid("not an endgame database"))?;
+        if entries.len() % ENTRY_SIZE != 0 {
+            return Err(invalid("truncated endgame database"));
+        }
+        let mut db = EndgameDb::new(max_empty as u32);
+        for entry in entries.chunks(ENTRY_SIZE) {
+            let key = u128::from_le_bytes(entry[0..16].try_into().unwrap());
+            db.entries.insert(key, entry[16] as i8);
+        }
+        Ok(db)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        EndgameDb::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+// Evaluates positions with 'evaluator' but gives the searches the exact values of the
+// positions in the database.
+pub struct ProbingEval<'a, E> {
+    pub evaluator: E,
+    db: &'a EndgameDb,
+}
+
+impl<'a, E> ProbingEval<'a, E> {
+    pub fn new(evaluator: E, db: &'a EndgameDb) -> Self {
+        ProbingEval {
+            evaluator,
+            db,
+        }
+    }
+}
+
+impl<'a, E: Evaluator<Stack4>> Evaluator<Stack4> for ProbingEval<'a, E> {
+    fn value(&self, board: &Stack4, player: Player) -> f64 {
+        self.exact_value(board, player).unwrap_or_else(|| self.evaluator.value(board, player))
+    }
+
+    // only the boards that are not in the database are given to the evaluator.
+    fn values(&self, boards: &Vec<Stack4>, player: Player) -> Vec<f64> {
+        let exact: Vec<Option<f64>> = boards.iter().map(|board| self.exact_value(board, player)).collect();
+        let unknown: Vec<Stack4> = boards.iter().zip(&exact).filter(|(_, v)| v.is_none()).map(|(board, _)| *board).collect();
+        let mut values = self.evaluator.values(&unknown, player).into_iter();
+        exact.into_iter().map(|v| v.unwrap_or_else(|| values.next().unwrap())).collect()
+    }
+
+    fn exact_value(&self, board: &Stack4, player: Player) -> Option<f64> {
+        let value = match self.db.value(board)? {
+            1 => 1./0.,
+            -1 => -1./0.,
+            _ => 0.0,
+        };
+        Some(if player == board.cur_player() {value} else {-value})
+    }
+
+    fn gradient(&self, board: &Stack4, player: Player) -> Vec<f64> {
+        self.evaluator.gradient(board, player)
+    }
+
+    fn apply_update(&mut self, update: &[f64]) {
+        self.evaluator.apply_update(update)
+    }
+
+    fn get_params(&self) -> Vec<f64> {
+        self.evaluator.get_params()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::agents::{Agent, CompositeAgent};
+    use crate::evaluators::SimpleEval;
+    use crate::search::{abnegamax, SearchOptions, TranspositionTable};
+
+    // the keys are uids of Stack4 which are the boards.
+    fn board_from_key(key: u128) -> Stack4 {
+        let cells: Vec<u8> = (0..64).map(|i| 3 & (key >> (2*(i/8+i%8*8))) as u8).collect();
+        let red = cells.iter().filter(|&&c| c == 1).count();
+        let yellow = cells.iter().filter(|&&c| c == 2).count();
+        let player = if red == yellow {Player::Red} else {Player::Yellow};
+        Stack4::from_cells(&cells, player).unwrap()
+    }
+
+    #[test]
+    fn exact_values() {
+        fastrand::seed(5);
+        let mut db = EndgameDb::new(7);
+        db.generate(3);
+        assert!(!db.is_empty());
+        let db = EndgameDb::from_bytes(&db.to_bytes()).unwrap();
+        let evaluator = SimpleEval::new();
+        let mut checked = 0;
+        for (&key, &value) in db.entries.iter().take(200) {
+            let board = board_from_key(key);
+            let player = board.cur_player();
+            // a search to the end of the game finds the same value.
+            let v = abnegamax(&board, NB_CELLS-board.nb_moves, 0, &evaluator, player, Some(&TranspositionTable::new()), SearchOptions::default());
+            assert_eq!(v, ProbingEval::new(evaluator, &db).value(&board, player));
+            for symmetry in board.symmetries() {
+                assert_eq!(db.value(&symmetry), Some(value));
+            }
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn probing_agent() {
+        fastrand::seed(1);
+        // a random game where no player gets the chance to win until 9 cells are left, solving the position
+        // stores every position on the way.
+        let threat = |board: &Stack4, player| board.legal_actions().any(|a| board.is_winning_action(a, player));
+        let mut board = Stack4::new();
+        while board.nb_moves < NB_CELLS-9 {
+            let actions: Vec<_> = board.legal_actions().filter(|&a| {
+                let mut next = board;
+                next.play_action(a);
+                next.game_state() == GameState::InProgress && !threat(&next, Player::Red) && !threat(&next, Player::Yellow)
+            }).collect();
+            if actions.is_empty() {
+                board = Stack4::new();
+                continue;
+            }
+            board.play_action(actions[fastrand::usize(0..actions.len())]);
+        }
+        let mut db = EndgameDb::new(9);
+        db.solve(&board);
+        let evaluator = SimpleEval::new();
+        let probing = ProbingEval::new(SimpleEval::new(), &db);
+        let agent = CompositeAgent::new(&evaluator, 2, 0, 2);
+        let probing_agent = CompositeAgent::new(&evaluator, 2, 0, 2).with_simple_eval(ProbingEval::new(SimpleEval::new(), &db));
+        // a decided position where the search is too shallow to see the end of the game but the database knows it.
+        let (board, value) = db.entries.iter().filter(|(_, &v)| v != 0)
+            .map(|(&key, &v)| (board_from_key(key), v))
+            .find(|(board, _)| agent.analyse(board, board.cur_player()).unwrap().score.is_finite())
+            .unwrap();
+        let result = probing_agent.analyse(&board, board.cur_player()).unwrap();
+        assert_eq!(result.score, if value == 1 {1./0.} else {-1./0.});
+
+        // the batched values probe the database as well.
+        let mut boards: Vec<Stack4> = db.entries.keys().take(20).map(|&key| board_from_key(key)).collect();
+        boards.push(Stack4::new());
+        let values: Vec<f64> = boards.iter().map(|board| probing.value(board, Player::Red)).collect();
+        assert_eq!(probing.values(&boards, Player::Red), values);
+        assert!(values.iter().any(|v| v.is_infinite()));
+    }
+}
@@ -2,10 +2,12 @@ pub mod cnn;
 pub mod consequtive;
 pub mod lines;
 pub mod simple;
+pub mod threats;
 
 pub use consequtive::ConsequtiveEval;
 pub use lines::LinesEval;
 pub use simple::SimpleEval;
+pub use threats::ThreatEval;
 pub use cnn::CNNEval;
 use serde::{Serialize, Deserialize};
 
@@ -14,7 +16,8 @@ use crate::games::connect4::Connect4;
 use crate::games::stack4::Stack4;
 
 
-pub trait Evaluator<T> where T: Game {
+// Evaluators are shared between the threads of a parallel search.
+pub trait Evaluator<T>: Sync where T: Game {
 
     // the estimated value at the position 'board'.
     // it must always return -infinity on loss and +infinity on win.    
@@ -29,6 +32,12 @@ pub trait Evaluator<T> where T: Game {
         vs
     }
 
+    // The value of the position if it is known exactly, for example from an endgame database.
+    // Searches use it instead of searching the position.
+    fn exact_value(&self, _board: &T, _player: Player) -> Option<f64> {
+        None
+    }
+
     fn gradient(&self, board: &T, player: Player) -> Vec<f64>;
     fn apply_update(&mut self, update: &[f64]);
     //fn update(&mut self, board: &Connect4, player: Player, target_av: f64, learning_rate: f64);
@@ -42,6 +51,12 @@ pub enum Stack4Evaluators {
     CNN(CNNEval)
 }
 
+// The evaluators that work for every game.
+#[derive(Serialize, Deserialize)]
+pub enum GenericEvaluators {
+    Simple(SimpleEval),
+    CNN(CNNEval)
+}
 
 #[derive(Serialize, Deserialize)]
 pub enum Connect4Evaluators {
@@ -49,6 +64,7 @@ pub enum Connect4Evaluators {
     Lines(LinesEval),
     CNN(CNNEval),
     Consequtive(ConsequtiveEval),
+    Threats(ThreatEval),
 }
 
 impl Evaluator<Connect4> for Connect4Evaluators {
@@ -58,6 +74,7 @@ impl Evaluator<Connect4> for Connect4Evaluators {
             Connect4Evaluators::Lines(ref eval) => {eval.value(board, player)},
             Connect4Evaluators::CNN(ref eval) => {eval.value(board, player)},
             Connect4Evaluators::Consequtive(ref eval) => {eval.value(board, player)},
+            Connect4Evaluators::Threats(ref eval) => {eval.value(board, player)},
         }
     }
     fn values(&self, boards: &Vec<Connect4>, player: Player) -> Vec<f64> {
@@ -66,6 +83,7 @@ impl Evaluator<Connect4> for Connect4Evaluators {
             Connect4Evaluators::Lines(ref eval) => {eval.values(boards, player)},
             Connect4Evaluators::CNN(ref eval) => {eval.values(boards, player)},
             Connect4Evaluators::Consequtive(ref eval) => {eval.values(boards, player)},
+            Connect4Evaluators::Threats(ref eval) => {eval.values(boards, player)},
         }
     }
     fn gradient(&self, board: &Connect4, player: Player) -> Vec<f64> {
@@ -74,6 +92,7 @@ impl Evaluator<Connect4> for Connect4Evaluators {
             Connect4Evaluators::Lines(ref eval) => {eval.gradient(board, player)},
             Connect4Evaluators::CNN(ref eval) => {eval.gradient(board, player)},
             Connect4Evaluators::Consequtive(ref eval) => {eval.gradient(board, player)},
+            Connect4Evaluators::Threats(ref eval) => {eval.gradient(board, player)},
         }
     }
     fn apply_update(&mut self, update: &[f64]) {
@@ -82,6 +101,7 @@ impl Evaluator<Connect4> for Connect4Evaluators {
             Connect4Evaluators::Lines(ref mut eval) => {<LinesEval as Evaluator<Connect4>>::apply_update(eval, update)},
             Connect4Evaluators::CNN(ref mut eval) => {<CNNEval as Evaluator<Connect4>>::apply_update(eval, update)},
             Connect4Evaluators::Consequtive(ref mut eval) => {<ConsequtiveEval as Evaluator<Connect4>>::apply_update(eval, update)},
+            Connect4Evaluators::Threats(ref mut eval) => {eval.apply_update(update)},
         }
     }
     fn get_params(&self) -> Vec<f64> {
@@ -90,6 +110,7 @@ impl Evaluator<Connect4> for Connect4Evaluators {
             Connect4Evaluators::Lines(ref eval) => {<LinesEval as Evaluator<Connect4>>::get_params(eval)},
             Connect4Evaluators::CNN(ref eval) => {<CNNEval as Evaluator<Connect4>>::get_params(eval)},
             Connect4Evaluators::Consequtive(ref eval) => {<ConsequtiveEval as Evaluator<Connect4>>::get_params(eval)},
+            Connect4Evaluators::Threats(ref eval) => {eval.get_params()},
         }
     }
 }
@@ -131,4 +152,37 @@ impl Evaluator<Stack4> for Stack4Evaluators {
             Stack4Evaluators::CNN(ref eval) => {<CNNEval as Evaluator<Stack4>>::get_params(eval)},
         }
     }
-}
\ No newline at end of file
+}
+
+impl<G: Game> Evaluator<G> for GenericEvaluators {
+    fn value(&self, board: &G, player: Player) -> f64 {
+        match self {
+            GenericEvaluators::Simple(ref eval) => {eval.value(board, player)},
+            GenericEvaluators::CNN(ref eval) => {eval.value(board, player)},
+        }
+    }
+    fn values(&self, boards: &Vec<G>, player: Player) -> Vec<f64> {
+        match self {
+            GenericEvaluators::Simple(ref eval) => {eval.values(boards, player)},
+            GenericEvaluators::CNN(ref eval) => {eval.values(boards, player)},
+        }
+    }
+    fn gradient(&self, board: &G, player: Player) -> Vec<f64> {
+        match self {
+            GenericEvaluators::Simple(ref eval) => {eval.gradient(board, player)},
+            GenericEvaluators::CNN(ref eval) => {eval.gradient(board, player)},
+        }
+    }
+    fn apply_update(&mut self, update: &[f64]) {
+        match self {
+            GenericEvaluators::Simple(ref mut eval) => {<SimpleEval as Evaluator<G>>::apply_update(eval,update)},
+            GenericEvaluators::CNN(ref mut eval) => {<CNNEval as Evaluator<G>>::apply_update(eval,update)},
+        }
+    }
+    fn get_params(&self) -> Vec<f64> {
+        match self {
+            GenericEvaluators::Simple(ref eval) => {<SimpleEval as Evaluator<G>>::get_params(eval)},
+            GenericEvaluators::CNN(ref eval) => {<CNNEval as Evaluator<G>>::get_params(eval)},
+        }
+    }
+}
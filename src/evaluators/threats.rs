@@ -0,0 +1,51 @@
+use serde::{Serialize, Deserialize};
+use crate::games::{Player, GameState};
+use crate::games::connect4::Connect4;
+use crate::games::connect4::threats::{self, NB_FEATURES};
+use super::Evaluator;
+
+// Weighs the features of the threat analysis, see games::connect4::threats.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ThreatEval {
+    pub params: Vec<f64>,
+}
+
+impl Evaluator<Connect4> for ThreatEval {
+    fn value(&self, board: &Connect4, player: Player) -> f64 {
+        match board.game_state {
+            GameState::Won(p) => {
+                if p == player {1./0.} else {-1./0.}
+            },
+            GameState::Draw => 0.0,
+            GameState::InProgress => {
+                let features = threats::analyse(board).features(player);
+                features.iter().zip(self.params.iter()).map(|(f, v)| f*v).sum()
+            },
+        }
+    }
+    fn gradient(&self, board: &Connect4, player: Player) -> Vec<f64> {
+        threats::analyse(board).features(player).to_vec()
+    }
+    fn apply_update(&mut self, update: &[f64]) {
+        for (p, d) in self.params.iter_mut().zip(update) {
+            *p += d;
+        }
+    }
+    fn get_params(&self) -> Vec<f64> {
+        self.params.clone()
+    }
+}
+
+impl ThreatEval {
+    pub fn new() -> Self {
+        ThreatEval {
+            params: vec![0.0; NB_FEATURES],
+        }
+    }
+}
+
+impl Default for ThreatEval {
+    fn default() -> Self {
+        Self::new()
+    }
+}
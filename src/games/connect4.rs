@@ -1,13 +1,15 @@
 
 use std::fmt;
 use serde::{Serialize, Deserialize};
-use crate::games::{Player, GameState};
-use crate::games::Game;
+use crate::games::{Player, GameState, PlayError};
+use crate::games::{ActionBuffer, Game, zobrist_keys};
+use crate::games::notation::{self, ActionNotation, NotationError};
 use crate::matchmaker::PlayableGame;
-use smallvec::SmallVec;
 use std::io;
 use std::io::BufRead;
+use std::str::FromStr;
 
+pub mod threats;
 
 pub const BOARD_WIDTH: usize = 7;
 pub const BOARD_HEIGHT: usize = 6;
@@ -17,133 +19,219 @@ pub const REWARD_LOSE: f64 = -1.0;
 pub const REWARD_WIN: f64 = 1.0;
 pub const REWARD_DRAW: f64 = 0.0;
 
-pub type Action = usize; // a value in the range of [0,BOARD_WIDTH)
+pub type Action = usize; // a value in the range of [0,W)
 
+// The standard 7x6 board where four in a row wins.
+pub type Connect4 = Connect4Board<BOARD_WIDTH, BOARD_HEIGHT, 4>;
 
+// indexed by player and then by the bit index of the cell.
+const ZOBRIST: [[u64; 128]; 2] = zobrist_keys(0xc4);
+
+// A board that is W columns wide and H rows high where K in a row wins.
+// Both bitboards have to fit in a u128, so W*(H+1) can be at most 128.
 #[derive(Clone, Copy, Serialize, Deserialize)]
-pub struct Connect4 {
-    // tile on board takes up 2 bits, 0 for empty, 1 for red, 2 for yellow. 
-    // starts in bottom left corner and goes row by row.
-    pub board: u128,
+pub struct Connect4Board<const W: usize, const H: usize, const K: usize> {
+    // one bitboard per player, bitboards[0] for red and bitboards[1] for yellow.
+    // bit x*(H+1)+y is set if the player has a piece at (x, y).
+    pub bitboards: [u128; 2],
+    // number of pieces in each column.
+    #[serde(with = "heights")]
+    pub heights: [u8; W],
+    pub hash: u64,
     pub cur_player: Player,
     pub game_state: GameState,
     pub nb_moves: u32,
 }
 
-impl Connect4 {
+// serde only implements arrays up to a fixed length, so the heights are stored as a sequence.
+mod heights {
+    use serde::{Serializer, Deserializer, Deserialize};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer, const W: usize>(heights: &[u8; W], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(heights)
+    }
 
+    pub fn deserialize<'de, D: Deserializer<'de>, const W: usize>(deserializer: D) -> Result<[u8; W], D::Error> {
+        let heights = Vec::<u8>::deserialize(deserializer)?;
+        let len = heights.len();
+        heights.try_into().map_err(|_| D::Error::invalid_length(len, &"one height per column"))
+    }
+}
+
+impl<const W: usize, const H: usize, const K: usize> Connect4Board<W, H, K> {
+    // Every column takes up H+1 bits of a bitboard, the extra bit on top of each column
+    // is always empty so that shifting a bitboard never carries a piece into the next column.
+    // Boards that do not fit fail to compile.
+    const COLUMN_BITS: usize = {
+        assert!(W > 0 && H > 0 && K > 0 && W*(H+1) <= 128, "the board does not fit in a u128");
+        H+1
+    };
+    const COLUMN_MASK: u128 = (1<<H)-1;
+    // the bottom cell of every column.
+    const BOTTOM: u128 = {
+        let mut bottom = 0;
+        let mut x = 0;
+        while x < W {
+            bottom |= 1 << (x*Self::COLUMN_BITS);
+            x += 1;
+        }
+        bottom
+    };
+
+    // Returns true if the player with a piece at 'piece_pos' has K in a row.
     pub fn player_won(&self, piece_pos: [usize; 2]) -> bool {
-        let directions: [[i32;2];4] = [[1,0],[0,1],[-1,1], [1,1]];
-        let player = self.get(piece_pos[0],piece_pos[1]);
-        for direction in directions {
-            let mut sm = 1;
-            for i in 1..4 {
-                let curx = direction[0]*i+piece_pos[0] as i32;
-                let cury = direction[1]*i+piece_pos[1] as i32;
-                if !self.in_board(curx, cury) {
-                    break;
-                } else if player as u8 != self.get(curx as usize,cury as usize) {
-                    break;
-                } 
-                sm += 1;
-            }
-            for i in 1..4 {
-                let i = -i;
-                let curx = direction[0]*i+piece_pos[0] as i32;
-                let cury = direction[1]*i+piece_pos[1] as i32;
-                if !self.in_board(curx, cury) {
-                    break;
-                } else if player as u8 != self.get(curx as usize,cury as usize) {
-                    break;
-                }
-                sm += 1;
+        match self.get(piece_pos[0], piece_pos[1]) {
+            0 => false,
+            p => Self::has_k_in_row(self.bitboards[p as usize-1]),
+        }
+    }
+
+    // Checks all four directions at once by shifting the bitboard onto itself.
+    fn has_k_in_row(bitboard: u128) -> bool {
+        // vertical, horizontal, diagonal (\) and diagonal (/)
+        for shift in [1, Self::COLUMN_BITS, Self::COLUMN_BITS-1, Self::COLUMN_BITS+1] {
+            // bits that start a run of i+1 pieces.
+            let mut runs = bitboard;
+            for _ in 1..K {
+                runs &= runs >> shift;
             }
-            if sm >= 4 {
+            if runs != 0 {
                 return true;
             }
         }
         false
     }
 
-    pub fn is_winning_action(&self, action: Action, player: Player) -> bool {
-        let mut resulting_board = self.clone();
-        resulting_board.play_action(action);
-        resulting_board.game_state == GameState::Won(player)
-    } 
-
     pub fn in_board(&self, x:i32,y:i32) -> bool {
-        x >= 0 && y >= 0 && x < BOARD_WIDTH as i32 && y < BOARD_HEIGHT as i32 
+        x >= 0 && y >= 0 && x < W as i32 && y < H as i32 
     }
 
-    // Returns where piece will be placed if 'action' is played.
-    fn action_pos(&self, action: Action) -> [usize; 2] {
-        for cur_y in 0..BOARD_HEIGHT {
-            if  self.get(action,cur_y) == 0 {
-                return [action, cur_y];
-            }
+    // The i:th column when going from the center and outwards, alternating between right and left.
+    pub fn center_column(i: usize) -> usize {
+        if i%2 == 1 {
+            (W-1)/2 + i/2 + 1
+        } else {
+            (W-1)/2 - i/2
         }
-        unimplemented!()
     }
 
-    // Returns where piece placed from last played action 'action'
-    fn pos_from_action(&self, action: Action) -> [usize; 2] {
-        for cur_y in 1..BOARD_HEIGHT {
-            if  self.get(action,cur_y) == 0 {
-                return [action, cur_y-1];
+    fn bit(x: usize, y: usize) -> u128 {
+        1 << (x*Self::COLUMN_BITS+y)
+    }
+
+    // Computes the Zobrist hash from scratch.
+    fn zobrist(bitboards: &[u128; 2]) -> u64 {
+        let mut hash = 0;
+        for (keys, bitboard) in ZOBRIST.iter().zip(bitboards) {
+            let mut bitboard = *bitboard;
+            while bitboard != 0 {
+                hash ^= keys[bitboard.trailing_zeros() as usize];
+                bitboard &= bitboard-1;
             }
         }
-        [action, BOARD_HEIGHT-1] 
+        hash
+    }
+
+    // The red pieces together with a bit on top of every column, which tells the height of the column
+    // and with it where the yellow pieces are. Unique for every position and fits in W*(H+1) bits.
+    fn encode(bitboards: [u128; 2]) -> u128 {
+        bitboards[0] | ((bitboards[0] | bitboards[1]) + Self::BOTTOM)
     }
 
     pub fn is_full(&self) -> bool {
-        !(0..N_ACTIONS).any(|action|self.is_valid_move(action))
+        self.nb_moves as usize == W*H
     }
 
     pub fn is_valid_move(&self, action: Action) -> bool {
-        assert!(action < BOARD_WIDTH);
-        self.get(action,BOARD_HEIGHT-1) == 0
+        assert!(action < W);
+        (self.heights[action] as usize) < H
     }
 
     // mirrors board around the middle of the board.
-    // assumes BOARD_WIDTH = 7. so if BOARD_WIDTH changes then so must this function
-    pub fn symmetry(&self) -> Connect4 {
-        //let col_mask: u128 = (((((((((3<<BOARD_WIDTH*2)+3)<<BOARD_WIDTH*2)+3)<<BOARD_WIDTH*2)+3)<<BOARD_WIDTH*2)+3)<<BOARD_WIDTH*2)+3;
-        let mut col_mask: u128 = 0;
-        for _ in 0..BOARD_HEIGHT {
-            col_mask = (col_mask<<BOARD_WIDTH*2)+3;
-        }
-        let mut new_board:u128 = 0;
-        for i in 0..3 {
-            new_board += (self.board&(col_mask<<(i*2)))<<((3-i)*4);
-        }
-        for i in 0..3 {
-            new_board += (self.board&(col_mask<<(4+i)*2))>>(i+1)*4;
-        }
-        new_board += self.board&(col_mask<<3*2);
-        
-        Connect4 {
-            board: new_board,
+    pub fn symmetry(&self) -> Self {
+        let bitboards = self.bitboards.map(Self::mirror_bitboard);
+        let mut heights = self.heights;
+        heights.reverse();
+        Connect4Board {
+            bitboards,
+            heights,
+            hash: Self::zobrist(&bitboards),
             ..*self
         }
     }
 
+    // Moves every column of the bitboard to the other side of the middle.
+    fn mirror_bitboard(bitboard: u128) -> u128 {
+        let mut mirrored = 0;
+        for x in 0..W {
+            let column = (bitboard >> (x*Self::COLUMN_BITS)) & Self::COLUMN_MASK;
+            mirrored |= column << ((W-1-x)*Self::COLUMN_BITS);
+        }
+        mirrored
+    }
+
+    // Sets the tile at (x, y) to v where 0 is empty, 1 is red and 2 is yellow.
+    // The caller is responsible for not leaving pieces floating in the air.
     pub fn set(&mut self, x: usize, y: usize, v: u8) {
-        let k = 2*(x+y*BOARD_WIDTH);
-        let mask = 3 << k;
-        self.board = (self.board & (!mask)) + ((v as u128) << k);
+        let old = self.get(x, y);
+        if old != 0 {
+            self.hash ^= ZOBRIST[old as usize-1][x*Self::COLUMN_BITS+y];
+        }
+        let bit = Self::bit(x, y);
+        self.bitboards[0] &= !bit;
+        self.bitboards[1] &= !bit;
+        if v != 0 {
+            self.bitboards[v as usize-1] |= bit;
+            self.hash ^= ZOBRIST[v as usize-1][x*Self::COLUMN_BITS+y];
+        }
+        let column = ((self.bitboards[0] | self.bitboards[1]) >> (x*Self::COLUMN_BITS)) & Self::COLUMN_MASK;
+        self.heights[x] = column.count_ones() as u8;
     }
 
     pub fn get(&self, x: usize, y:usize) -> u8 {
-        3 & (self.board >> (2*(x+y*BOARD_WIDTH))) as u8
+        let bit = Self::bit(x, y);
+        if self.bitboards[0] & bit != 0 {
+            1
+        } else if self.bitboards[1] & bit != 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    // Builds a position from the cells column by column from the bottom, cell (x, y) is at index x*H+y.
+    pub fn from_cells(cells: &[u8], player: Player) -> Result<Self, NotationError> {
+        if cells.len() != W*H {
+            return Err(NotationError::InvalidPosition(format!("expected {} cells but got {}", W*H, cells.len())));
+        }
+        let mut board = Self::new();
+        board.nb_moves = notation::count_moves(cells, player)?;
+        board.cur_player = player;
+        for (x, column) in cells.chunks(H).enumerate() {
+            let height = column.iter().take_while(|&&cell| cell != 0).count();
+            if column[height..].iter().any(|&cell| cell != 0) {
+                return Err(NotationError::InvalidPosition(format!("floating piece in column {}", x+1)));
+            }
+            for (y, &cell) in column.iter().enumerate() {
+                board.set(x, y, cell);
+            }
+        }
+        let won = |p: usize| Self::has_k_in_row(board.bitboards[p]);
+        board.game_state = notation::position_state(won(0), won(1), board.is_full(), player)?;
+        Ok(board)
     }
 }
 
-impl Game for Connect4 {
+impl<const W: usize, const H: usize, const K: usize> Game for Connect4Board<W, H, K> {
     type Action = usize;
+    type Undo = usize; // the column.
 
     fn new() -> Self {
-        Connect4 {
-            board: 0,
+        Connect4Board {
+            bitboards: [0; 2],
+            heights: [0; W],
+            hash: 0,
             cur_player: Player::Red,
             game_state: GameState::InProgress,
             nb_moves: 0,
@@ -151,16 +239,18 @@ impl Game for Connect4 {
     }
 
     // Plays action for player self.cur_player
-    fn play_action(&mut self, action: Action) {
+    fn play_action(&mut self, action: Action) -> Action {
         assert_eq!(self.game_state, GameState::InProgress);
-        if !self.is_valid_move(action) {
-            return
-        }
-        let ap = self.action_pos(action);
-        self.set(ap[0],ap[1], self.cur_player as u8);
+        assert!(self.is_valid_move(action), "column {} is full", action);
+        let p = self.cur_player as usize-1;
+        let cell = action*Self::COLUMN_BITS+self.heights[action] as usize;
+        let bitboard = &mut self.bitboards[p];
+        *bitboard |= 1 << cell;
+        self.hash ^= ZOBRIST[p][cell];
+        self.heights[action] += 1;
         self.nb_moves += 1;
 
-        if self.player_won(ap) {
+        if Self::has_k_in_row(*bitboard) {
             self.game_state = GameState::Won(self.cur_player);
         } else if self.is_full() {
             self.game_state = GameState::Draw;
@@ -168,37 +258,43 @@ impl Game for Connect4 {
             self.game_state = GameState::InProgress;
         }
         self.cur_player = !self.cur_player;
+        action
+    }
+
+    fn try_play_action(&mut self, action: Action) -> Result<Action, PlayError> {
+        if self.game_state != GameState::InProgress {
+            Err(PlayError::GameOver)
+        } else if action >= W {
+            Err(PlayError::OutOfRange)
+        } else if !self.is_valid_move(action) {
+            Err(PlayError::ColumnFull)
+        } else {
+            Ok(self.play_action(action))
+        }
     }
 
     // Reverses last action if the last action is last_action.
     fn reverse_last_action(&mut self, last_action: Action) {
-        let ap = self.pos_from_action(last_action);
-        self.set(ap[0],ap[1],0);
+        self.heights[last_action] -= 1;
+        let cell = last_action*Self::COLUMN_BITS+self.heights[last_action] as usize;
+        // the last action was played by the opponent of the player to move.
+        let p = !self.cur_player as usize-1;
+        self.bitboards[p] &= !(1 << cell);
+        self.hash ^= ZOBRIST[p][cell];
         self.game_state = GameState::InProgress;
         self.cur_player = !self.cur_player;
         self.nb_moves -= 1;
     }
 
-    fn legal_actions(&self) -> Box<dyn Iterator<Item=Action>> {
-        let mut winning_moves = SmallVec::<[Action; BOARD_WIDTH]>::new();
-
-        // moves that block the opponent from winning next turn.
-        let mut blocking_moves = SmallVec::<[Action; BOARD_WIDTH]>::new();
-        let moves = [3, 4, 2, 5, 1, 6, 0];
-        //let moves = [0, 1, 2, 3, 4, 5, 6];
-        let mut v = SmallVec::<[Action; BOARD_WIDTH]>::new(); 
-        for i in moves {
-            if self.is_valid_move(i) {
-                if self.is_winning_action(i, self.cur_player) {
-                    winning_moves.push(i);
-                } else if self.is_winning_action(i, !self.cur_player) {
-                    blocking_moves.push(i);
-                } else {
-                    v.push(i);
-                }
-            }
-        }
-        Box::new(winning_moves.into_iter().chain(blocking_moves.into_iter()).chain(v.into_iter()))
+    // the columns from the center and outwards.
+    fn generate_actions(&self, actions: &mut ActionBuffer<Action>) {
+        actions.extend((0..W).map(Self::center_column).filter(|&x| self.is_valid_move(x)));
+    }
+
+    // Returns true if 'player' would get four in a row by dropping a piece in column 'action'.
+    fn is_winning_action(&self, action: Action, player: Player) -> bool {
+        let bitboard = self.bitboards[player as usize-1] | Self::bit(action, self.heights[action] as usize);
+        Self::has_k_in_row(bitboard)
     }
 
     fn game_state(&self) -> GameState {
@@ -210,28 +306,45 @@ impl Game for Connect4 {
     }
 
     fn vectorize(&self, player: Player) -> Vec<f64> {
-        let mut v = Vec::with_capacity(BOARD_WIDTH*BOARD_HEIGHT);
-        let mut board = self.board;
-        for _ in 0..BOARD_WIDTH {
-            for _ in 0..BOARD_HEIGHT {
-                let cur = board as u8 & 3;
-                if cur == player as u8 {
+        let mut v = Vec::with_capacity(W*H);
+        let own = self.bitboards[player as usize-1];
+        let opponent = self.bitboards[!player as usize-1];
+        // row by row starting in the bottom left corner.
+        for y in 0..H {
+            for x in 0..W {
+                let bit = Self::bit(x, y);
+                if own & bit != 0 {
                     v.push(1.0);
-                } else if cur == !player as u8 {
+                } else if opponent & bit != 0 {
                     v.push(-1.0);
                 } else {
                     v.push(0.0);
                 }
-                board >>= 2;
             }
         }
         v
     }
     fn shape() -> [usize; 2] {
-        [BOARD_WIDTH, BOARD_HEIGHT]
+        [W, H]
+    }
+    fn action_index(action: Action) -> usize {
+        action
+    }
+    fn index_to_action(index: usize) -> Action {
+        index
+    }
+    fn action_space() -> usize {
+        W
     }
     fn uid(&self) -> u128 {
-        self.board
+        Self::encode(self.bitboards)
+    }
+    // only mirrors the bitboards instead of building the mirrored board.
+    fn canonical_uid(&self) -> u128 {
+        self.uid().min(Self::encode(self.bitboards.map(Self::mirror_bitboard)))
+    }
+    fn hash(&self) -> u64 {
+        self.hash
     }
     fn symmetries(&self) -> Vec<Self> {
         vec![self.clone(), self.symmetry()]
@@ -242,11 +355,11 @@ impl Game for Connect4 {
 }
 
 
-impl fmt::Debug for Connect4 {
+impl<const W: usize, const H: usize, const K: usize> fmt::Debug for Connect4Board<W, H, K> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut s = String::new();
-        for y in (0..BOARD_HEIGHT).rev() {
-            for x in 0..BOARD_WIDTH {
+        for y in (0..H).rev() {
+            for x in 0..W {
                 match self.get(x,y) {
                     0 => s.push_str("# "),
                     1 => {
@@ -263,23 +376,64 @@ impl fmt::Debug for Connect4 {
     }
 }
 
-impl PlayableGame for Connect4 {
+// The FEN-like form of the position, see the notation module.
+impl<const W: usize, const H: usize, const K: usize> fmt::Display for Connect4Board<W, H, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        notation::write_grid(f, W, H, |x, y| self.get(x, y), self.cur_player)
+    }
+}
+
+impl<const W: usize, const H: usize, const K: usize> FromStr for Connect4Board<W, H, K> {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<Self, NotationError> {
+        let (cells, player) = notation::parse_grid(s, W, H)?;
+        Self::from_cells(&cells, player)
+    }
+}
+
+// Columns are counted from 1 and written as a single character, boards wider
+// than 9 columns continue with the letters a, b, ...
+impl<const W: usize, const H: usize, const K: usize> ActionNotation for Connect4Board<W, H, K> {
+    fn format_action(action: Action) -> String {
+        char::from_digit(action as u32+1, 36).expect("column fits in a character").to_string()
+    }
+
+    fn parse_action(s: &str) -> Result<Action, NotationError> {
+        let mut chars = s.chars();
+        match (chars.next().and_then(|c| c.to_digit(36)), chars.next()) {
+            (Some(d), None) if d > 0 => Ok(d as usize-1),
+            _ => Err(NotationError::InvalidAction(s.to_string())),
+        }
+    }
+
+    fn format_actions(actions: &[Action]) -> String {
+        actions.iter().map(|&a| Self::format_action(a)).collect()
+    }
+
+    // whitespace between the columns is allowed.
+    fn parse_actions(s: &str) -> Result<Vec<Action>, NotationError> {
+        s.chars().filter(|c| !c.is_whitespace()).map(|c| Self::parse_action(&c.to_string())).collect()
+    }
+}
+
+impl<const W: usize, const H: usize, const K: usize> PlayableGame for Connect4Board<W, H, K> {
     // returns (action, is_reverse)
     fn get_action_from_user(&self) -> (Action, bool) {
         let stdin = io::stdin();
         for line in stdin.lock().lines() {
             let line = line.unwrap();
-            if line.as_bytes()[0] == 'z' as u8 {
+            if line.starts_with('z') {
                 return (0, true);
             } else if let Ok(a) = line.parse::<usize>() {
-                if a < BOARD_WIDTH {
+                if a < W {
                     if !self.is_valid_move(a) {
                         println!("Column alread full");
                         continue;
                     }
                     return (a, false);
                 } else {
-                    println!("Not in range 0..{}", BOARD_WIDTH);    
+                    println!("Not in range 0..{}", W);    
                 }
             } else {
                 println!("Invalid input: try again");
@@ -292,6 +446,8 @@ impl PlayableGame for Connect4 {
 #[cfg(test)] 
 mod test {
     use super::*;
+    use std::collections::HashMap;
+
     #[test]
     fn player_won() {
         let mut board = Connect4::new();
@@ -316,11 +472,11 @@ mod test {
             println!("{:?}", board);
             assert_eq!(board.game_state, GameState::InProgress);
         }
-        board.play_action(moves[moves.len()-1]);
+        let undo = board.play_action(moves[moves.len()-1]);
         println!("{:?}", board);
         println!("{:?}", board.game_state);
         assert_eq!(board.game_state, GameState::Won(Player::Yellow));
-        board.reverse_last_action(moves[moves.len()-1]);
+        board.reverse_last_action(undo);
         assert_eq!(board.game_state, GameState::InProgress);
         board.play_action(6);
         board.play_action(2);
@@ -333,9 +489,188 @@ mod test {
         board.play_action(0);
         board.play_action(0);
         let old_board = board.clone();
-        board.play_action(0);
-        board.reverse_last_action(0);
+        let undo = board.play_action(0);
+        board.reverse_last_action(undo);
         println!("{:?}\n{:?}",old_board,board);
-        assert_eq!(old_board.board, board.board);
+        assert_eq!(old_board.uid(), board.uid());
+        assert_eq!(old_board.heights, board.heights);
+    }
+
+    #[test]
+    fn diagonal_wins() {
+        // red builds a / diagonal from (0,0) to (3,3).
+        let mut board = Connect4::new();
+        for mv in [0,1,1,2,2,3,2,3,3,6] {
+            board.play_action(mv);
+            assert_eq!(board.game_state, GameState::InProgress);
+        }
+        assert!(board.is_winning_action(3, Player::Red));
+        assert!(!board.is_winning_action(3, Player::Yellow));
+        board.play_action(3);
+        assert_eq!(board.game_state, GameState::Won(Player::Red));
+
+        // the mirrored game wins along the \ diagonal.
+        let mut board = Connect4::new();
+        for mv in [6,5,5,4,4,3,4,3,3,0,3] {
+            board.play_action(mv);
+        }
+        assert_eq!(board.game_state, GameState::Won(Player::Red));
+
+        // pieces at the top of one column and the bottom of the next are not in a row.
+        let mut board = Connect4::new();
+        for mv in [0,1,0,1,0,1,1,0,1,0,1,0,2] {
+            board.play_action(mv);
+        }
+        assert_eq!(board.game_state, GameState::InProgress);
+        assert_eq!(board.symmetry().symmetry().uid(), board.uid());
+        assert_eq!(board.symmetry().get(6, 5), board.get(0, 5));
+    }
+
+    #[test]
+    fn zobrist_hash() {
+        let mut a = Connect4::new();
+        let mut b = Connect4::new();
+        for mv in [3, 2, 4, 2, 0] {
+            a.play_action(mv);
+        }
+        for mv in [4, 2, 3, 2, 0] {
+            b.play_action(mv);
+        }
+        assert_eq!(a.hash(), b.hash());
+        assert_eq!(a.hash(), Connect4::zobrist(&a.bitboards));
+        let mut mirrored = Connect4::new();
+        for mv in [3, 4, 2, 4, 6] {
+            mirrored.play_action(mv);
+        }
+        assert_eq!(a.symmetry().hash(), mirrored.hash());
+
+        let undo = a.play_action(1);
+        assert_ne!(a.hash(), b.hash());
+        a.reverse_last_action(undo);
+        assert_eq!(a.hash(), b.hash());
+        a.set(0, 0, 0);
+        a.set(0, 0, 1);
+        assert_eq!(a.hash(), b.hash());
+    }
+
+    #[test]
+    fn canonical_uid() {
+        fastrand::seed(3);
+        for _ in 0..20 {
+            let mut board = Connect4Board::<6, 5, 4>::new();
+            while board.game_state == GameState::InProgress {
+                let actions: Vec<_> = board.legal_actions().collect();
+                board.play_action(actions[fastrand::usize(0..actions.len())]);
+                let min = board.symmetries().iter().map(|b| b.uid()).min().unwrap();
+                assert_eq!(board.canonical_uid(), min);
+                assert_eq!(board.symmetry().canonical_uid(), min);
+            }
+        }
+        let mut a = Connect4::new();
+        let mut b = Connect4::new();
+        a.play_action(0);
+        b.play_action(6);
+        assert_ne!(a.uid(), b.uid());
+        assert_eq!(a.canonical_uid(), b.canonical_uid());
+    }
+
+    #[test]
+    fn other_sizes() {
+        // three in a row on a 5x4 board.
+        let mut board = Connect4Board::<5, 4, 3>::new();
+        for mv in [0, 0, 1, 1] {
+            board.play_action(mv);
+        }
+        assert!(board.is_winning_action(2, Player::Red));
+        board.play_action(2);
+        assert_eq!(board.game_state, GameState::Won(Player::Red));
+        assert_eq!(board.vectorize(Player::Red).len(), 20);
+        assert_eq!(Connect4Board::<5, 4, 3>::shape(), [5, 4]);
+
+        // five in a row on a 9x6 board, four is not enough.
+        let mut board = Connect4Board::<9, 6, 5>::new();
+        for mv in [0, 0, 1, 1, 2, 2, 3, 3] {
+            board.play_action(mv);
+        }
+        assert_eq!(board.game_state, GameState::InProgress);
+        board.play_action(4);
+        assert_eq!(board.game_state, GameState::Won(Player::Red));
+        assert_eq!(board.symmetry().get(8, 0), 1);
+        assert_eq!(board.symmetry().symmetry().uid(), board.uid());
+
+        let order: Vec<_> = (0..6).map(Connect4Board::<6, 5, 4>::center_column).collect();
+        assert_eq!(order, vec![2, 3, 1, 4, 0, 5]);
+        let mut board = Connect4Board::<6, 5, 4>::new();
+        board.play_action(5);
+        let copy: Connect4Board<6, 5, 4> = serde_json::from_str(&serde_json::to_string(&board).unwrap()).unwrap();
+        assert_eq!(copy.heights, board.heights);
+        assert_eq!(copy.hash(), board.hash());
+    }
+
+    #[test]
+    fn wide_boards() {
+        // the last column of a 9x7 board starts at bit 64.
+        let mut board = Connect4Board::<9, 7, 4>::new();
+        for mv in [8, 0, 7, 0, 6, 0] {
+            board.play_action(mv);
+        }
+        assert!(board.is_winning_action(5, Player::Red));
+        assert!(board.is_winning_action(0, Player::Yellow));
+        let mut mirrored = Connect4Board::<9, 7, 4>::new();
+        for mv in [0, 8, 1, 8, 2, 8] {
+            mirrored.play_action(mv);
+        }
+        assert_eq!(board.symmetry().uid(), mirrored.uid());
+        assert_eq!(board.symmetry().hash(), mirrored.hash());
+        assert_eq!(board.canonical_uid(), mirrored.canonical_uid());
+        assert_ne!(board.uid(), mirrored.uid());
+        let undo = board.play_action(5);
+        assert_eq!(board.game_state, GameState::Won(Player::Red));
+        board.reverse_last_action(undo);
+        board.play_action(1);
+        for _ in 0..6 {
+            board.play_action(8);
+        }
+        assert_eq!(board.try_play_action(8), Err(PlayError::ColumnFull));
+        assert_eq!(board.get(8, 6), 1);
+        assert_eq!(board.game_state, GameState::InProgress);
+        board.play_action(0);
+        assert_eq!(board.game_state, GameState::Won(Player::Yellow));
+    }
+
+    #[test]
+    fn uid_is_exact() {
+        // every position within 6 plies of the start of a 5x4 board, the uid has to tell them all apart.
+        fn visit(board: &mut Connect4Board<5, 4, 4>, depth: u32, uids: &mut HashMap<u128, Vec<u8>>) {
+            let cells: Vec<u8> = (0..5).flat_map(|x| (0..4).map(move |y| (x, y))).map(|(x, y)| board.get(x, y)).collect();
+            assert_eq!(*uids.entry(board.uid()).or_insert_with(|| cells.clone()), cells);
+            if depth == 0 || board.game_state != GameState::InProgress {
+                return;
+            }
+            for action in board.legal_actions().collect::<Vec<_>>() {
+                let undo = board.play_action(action);
+                visit(board, depth-1, uids);
+                board.reverse_last_action(undo);
+            }
+        }
+        let mut uids = HashMap::new();
+        visit(&mut Connect4Board::new(), 6, &mut uids);
+        assert!(uids.len() > 1000);
+    }
+
+    #[test]
+    fn try_play_action() {
+        let mut board = Connect4::new();
+        assert_eq!(board.try_play_action(7), Err(PlayError::OutOfRange));
+        for _ in 0..6 {
+            assert_eq!(board.try_play_action(0), Ok(0));
+        }
+        assert_eq!(board.try_play_action(0), Err(PlayError::ColumnFull));
+        assert_eq!(board.nb_moves, 6);
+        for mv in [1, 2, 1, 2, 1, 2, 1] {
+            board.try_play_action(mv).unwrap();
+        }
+        assert_eq!(board.try_play_action(3), Err(PlayError::GameOver));
+        assert_eq!(PlayError::ColumnFull.to_string(), "the column is full");
     }
-}
\ No newline at end of file
+}
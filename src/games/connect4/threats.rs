@@ -0,0 +1,191 @@
+// Threat analysis with the odd/even threat rules that decide most Connect4 endgames.
+//
+// A threat is an empty cell that completes a line of K for a player. Rows are counted from 1
+// at the bottom as in the literature, so a threat on the bottom row is odd. When the columns fill
+// up the player who has to play below a threat of the opponent loses, and with an even number of
+// rows the parity of the rows decides who that is. Red, who moves first, gets the odd cells when
+// it keeps the parity and yellow gets the even cells by always replying in the same column.
+//
+// The rules only look at threats that can not be played right away, a threat that can be played
+// is a win for the player to move or has to be blocked, which is up to a search.
+
+use crate::games::{GameState, Player};
+use super::Connect4Board;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Threat {
+    pub x: usize,
+    pub y: usize,
+    pub player: Player,
+    // the cell can be played now, it is the lowest empty cell of its column.
+    pub playable: bool,
+}
+
+impl Threat {
+    pub fn odd(&self) -> bool {
+        // y is counted from 0.
+        (self.y+1)%2 == 1
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ThreatAnalysis {
+    // the threats of both players ordered by column and then by row.
+    pub threats: Vec<Threat>,
+    // the player who wins the fight for the last cells when both keep to the parity, yellow by
+    // default and red when it has an odd threat that is not above an even threat of yellow in the same column.
+    pub zugzwang: Player,
+    // the player who controls zugzwang if they also have a threat to win with at the end.
+    pub winner: Option<Player>,
+}
+
+// The number of features from 'features'.
+pub const NB_FEATURES: usize = 7;
+
+impl ThreatAnalysis {
+    pub fn count(&self, player: Player, odd: bool) -> usize {
+        self.threats.iter().filter(|t| t.player == player && !t.playable && t.odd() == odd).count()
+    }
+
+    pub fn playable(&self, player: Player) -> usize {
+        self.threats.iter().filter(|t| t.player == player && t.playable).count()
+    }
+
+    // Features for an evaluator seen from 'player': the odd, even and playable threats of 'player'
+    // and then of the opponent, the last is 1 if 'player' controls zugzwang and -1 otherwise.
+    pub fn features(&self, player: Player) -> [f64; NB_FEATURES] {
+        let counts = |p| [self.count(p, true) as f64, self.count(p, false) as f64, self.playable(p) as f64];
+        let [a, b, c] = counts(player);
+        let [d, e, f] = counts(!player);
+        [a, b, c, d, e, f, if self.zugzwang == player {1.0} else {-1.0}]
+    }
+}
+
+// Empty cells where 'player' would complete a line of K, as a bitboard in the layout of the board.
+pub fn threat_cells<const W: usize, const H: usize, const K: usize>(board: &Connect4Board<W, H, K>, player: Player) -> u128 {
+    let bitboard = board.bitboards[player as usize-1];
+    let occupied = board.bitboards[0] | board.bitboards[1];
+    if Connect4Board::<W, H, K>::has_k_in_row(bitboard) {
+        return 0;
+    }
+    let mut cells = 0;
+    for x in 0..W {
+        for y in board.heights[x] as usize..H {
+            let bit = Connect4Board::<W, H, K>::bit(x, y);
+            if occupied & bit == 0 && Connect4Board::<W, H, K>::has_k_in_row(bitboard | bit) {
+                cells |= bit;
+            }
+        }
+    }
+    cells
+}
+
+pub fn threats<const W: usize, const H: usize, const K: usize>(board: &Connect4Board<W, H, K>) -> Vec<Threat> {
+    let mut threats = Vec::new();
+    if board.game_state != GameState::InProgress {
+        return threats;
+    }
+    let cells = [Player::Red, Player::Yellow].map(|p| (p, threat_cells(board, p)));
+    for x in 0..W {
+        for y in board.heights[x] as usize..H {
+            let bit = Connect4Board::<W, H, K>::bit(x, y);
+            for &(player, cells) in &cells {
+                if cells & bit != 0 {
+                    threats.push(Threat { x, y, player, playable: y == board.heights[x] as usize });
+                }
+            }
+        }
+    }
+    threats
+}
+
+// Finds the threats of both players and who controls zugzwang. The rules assume that the number
+// of rows is even, as on the standard board, since otherwise following up in the same column
+// does not give yellow the even cells.
+pub fn analyse<const W: usize, const H: usize, const K: usize>(board: &Connect4Board<W, H, K>) -> ThreatAnalysis {
+    let threats = threats(board);
+    let waiting = |player: Player, odd: bool| threats.iter().filter(move |t| t.player == player && !t.playable && t.odd() == odd);
+    // an odd threat of red is refuted by an even threat of yellow lower down in the same column,
+    // red would have to play below it before reaching its own threat.
+    let red_wins = waiting(Player::Red, true).any(|r| !waiting(Player::Yellow, false).any(|y| y.x == r.x && y.y < r.y));
+    let (zugzwang, winner) = if red_wins {
+        (Player::Red, Some(Player::Red))
+    } else {
+        let yellow_wins = waiting(Player::Yellow, false).next().is_some();
+        (Player::Yellow, if yellow_wins {Some(Player::Yellow)} else {None})
+    };
+    ThreatAnalysis {
+        threats,
+        zugzwang,
+        winner,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::evaluators::{Evaluator, ThreatEval};
+    use crate::games::Game;
+    use crate::games::connect4::Connect4;
+
+    fn position(fen: &str) -> (Connect4, ThreatAnalysis) {
+        let board: Connect4 = fen.parse().unwrap();
+        let analysis = analyse(&board);
+        (board, analysis)
+    }
+
+    #[test]
+    fn threats_and_parity() {
+        // yellow threatens the bottom of the fourth column and red the cell above it.
+        let (_, analysis) = position("7/7/7/7/rrr4/yyy4 r");
+        assert_eq!(analysis.threats, [
+            Threat { x: 3, y: 0, player: Player::Yellow, playable: true },
+            Threat { x: 3, y: 1, player: Player::Red, playable: false },
+        ]);
+        assert!(analysis.threats[0].odd() && !analysis.threats[1].odd());
+        assert_eq!((analysis.playable(Player::Yellow), analysis.count(Player::Red, false)), (1, 1));
+        assert_eq!(analysis.features(Player::Red), [0.0, 1.0, 0.0, 0.0, 0.0, 1.0, -1.0]);
+        assert_eq!((analysis.zugzwang, analysis.winner), (Player::Yellow, None));
+
+        assert!(analyse(&Connect4::new()).threats.is_empty());
+        // every cell would complete a line for the winner, a finished game has no threats.
+        let (board, analysis) = position("7/7/7/r6/r6/ryyyyr1 r");
+        assert_eq!(board.game_state, GameState::Won(Player::Yellow));
+        assert!(analysis.threats.is_empty());
+        assert_eq!(threat_cells(&board, Player::Yellow), 0);
+    }
+
+    #[test]
+    fn zugzwang() {
+        // red has odd threats at both ends of the third row.
+        let (board, analysis) = position("7/7/7/1rrr3/1ryy3/yyry3 r");
+        assert_eq!(analysis.threats.iter().map(|t| (t.x, t.y, t.player)).collect::<Vec<_>>(), [(0, 2, Player::Red), (4, 2, Player::Red)]);
+        assert_eq!(analysis.count(Player::Red, true), 2);
+        assert_eq!((analysis.zugzwang, analysis.winner), (Player::Red, Some(Player::Red)));
+        let cells = threat_cells(&board, Player::Red);
+        assert_eq!(cells.count_ones(), 2);
+        assert!(cells & 1<<2 != 0);
+
+        // the first column is blocked and yellow has an even threat below the other one.
+        let (_, analysis) = position("7/7/7/yrrr3/rryy1y1/yyry1r1 r");
+        assert_eq!(analysis.threats, [
+            Threat { x: 4, y: 1, player: Player::Yellow, playable: false },
+            Threat { x: 4, y: 2, player: Player::Red, playable: false },
+        ]);
+        assert_eq!((analysis.zugzwang, analysis.winner), (Player::Yellow, Some(Player::Yellow)));
+        assert_eq!(analysis.features(Player::Yellow)[NB_FEATURES-1], 1.0);
+    }
+
+    #[test]
+    fn threat_eval() {
+        let mut evaluator = ThreatEval::new();
+        // only the control of zugzwang counts.
+        evaluator.apply_update(&[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.5]);
+        let (board, _) = position("7/7/7/1rrr3/1ryy3/yyry3 r");
+        assert_eq!(evaluator.value(&board, Player::Red), 0.5);
+        assert_eq!(evaluator.value(&board, Player::Yellow), -0.5);
+        assert_eq!(evaluator.gradient(&board, Player::Red), [2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+        let (board, _) = position("7/7/7/r6/r6/ryyyyr1 r");
+        assert_eq!(evaluator.value(&board, Player::Red), -1./0.);
+    }
+}
@@ -0,0 +1,417 @@
+use serde::{Serialize, Deserialize};
+use crate::games::{Player, GameState, PlayError};
+use crate::games::{ActionBuffer, Game, zobrist_keys};
+use crate::games::notation::{ActionNotation, NotationError};
+use crate::matchmaker::PlayableGame;
+use std::fmt;
+use std::io::BufRead;
+
+// every bitboard is made up of this many u64's, so boards can have at most 256 cells.
+const WORDS: usize = 4;
+const MAX_CELLS: usize = 64*WORDS;
+
+type Action = (usize, usize);
+
+// indexed by player and then by x+y*M.
+const ZOBRIST: [[u64; MAX_CELLS]; 2] = zobrist_keys(0x6d);
+// a second set of keys for the boards that do not fit in the uid.
+const UID_KEYS: [[u64; MAX_CELLS]; 2] = zobrist_keys(0x6e);
+
+pub type TicTacToe = MNKGame<3, 3, 3>;
+// five in a row on a 9x9 board.
+pub type FiveInARow = MNKGame<9, 9, 5>;
+// free-style Gomoku where five or more in a row wins.
+pub type Gomoku = MNKGame<15, 15, 5>;
+
+// Pieces are placed on any empty cell of a board that is M wide and N high,
+// the first player to get K or more in a row wins.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct MNKGame<const M: usize, const N: usize, const K: usize> {
+    // one bitboard per player, bitboards[0] for red and bitboards[1] for yellow.
+    // bit x+y*M is set if the player has a piece at (x, y).
+    pub bitboards: [[u64; WORDS]; 2],
+    pub hash: u64,
+    pub cur_player: Player,
+    pub game_state: GameState,
+    pub nb_moves: u32,
+}
+
+impl<const M: usize, const N: usize, const K: usize> MNKGame<M, N, K> {
+    // Boards that do not fit fail to compile.
+    const CELLS: usize = {
+        assert!(M > 0 && N > 0 && K > 0 && M*N <= MAX_CELLS, "the board has too many cells");
+        M*N
+    };
+
+    pub fn player_won(&self, piece_pos: [usize; 2]) -> bool {
+        let player = self.get(piece_pos[0], piece_pos[1]);
+        player != 0 && self.longest_line(piece_pos, player) >= K
+    }
+
+    // The longest line of 'player' pieces through 'piece_pos', which counts as one of them.
+    fn longest_line(&self, piece_pos: [usize; 2], player: u8) -> usize {
+        let directions: [[i32;2];4] = [[1,0],[0,1],[-1,1], [1,1]];
+        let mut longest = 0;
+        for direction in directions {
+            let mut sm = 1;
+            for sign in [1, -1] {
+                for i in 1..K as i32 {
+                    let curx = sign*direction[0]*i+piece_pos[0] as i32;
+                    let cury = sign*direction[1]*i+piece_pos[1] as i32;
+                    if !Self::in_board(curx, cury) || player != self.get(curx as usize, cury as usize) {
+                        break;
+                    }
+                    sm += 1;
+                }
+            }
+            longest = longest.max(sm);
+        }
+        longest
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.nb_moves as usize == Self::CELLS
+    }
+
+    pub fn in_board(x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < M as i32 && y < N as i32
+    }
+
+    // Sets the tile at (x, y) to v where 0 is empty, 1 is red and 2 is yellow.
+    pub fn set(&mut self, x: usize, y: usize, v: u8) {
+        let i = x+y*M;
+        let old = self.get(x, y);
+        if old != 0 {
+            self.bitboards[old as usize-1][i/64] &= !(1 << (i%64));
+            self.hash ^= ZOBRIST[old as usize-1][i];
+        }
+        if v != 0 {
+            self.bitboards[v as usize-1][i/64] |= 1 << (i%64);
+            self.hash ^= ZOBRIST[v as usize-1][i];
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        let i = x+y*M;
+        if self.bitboards[0][i/64] >> (i%64) & 1 != 0 {
+            1
+        } else if self.bitboards[1][i/64] >> (i%64) & 1 != 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    // Computes a Zobrist hash from scratch.
+    fn zobrist(bitboards: &[[u64; WORDS]; 2], keys: &[[u64; MAX_CELLS]; 2]) -> u64 {
+        let mut hash = 0;
+        for (keys, bitboard) in keys.iter().zip(bitboards) {
+            for (w, word) in bitboard.iter().enumerate() {
+                let mut word = *word;
+                while word != 0 {
+                    hash ^= keys[w*64+word.trailing_zeros() as usize];
+                    word &= word-1;
+                }
+            }
+        }
+        hash
+    }
+
+    // Returns the board with the piece at (x, y) moved to f(x, y).
+    fn transform(&self, f: impl Fn(usize, usize) -> (usize, usize)) -> Self {
+        let mut board = MNKGame {
+            bitboards: [[0; WORDS]; 2],
+            hash: 0,
+            ..*self
+        };
+        for x in 0..M {
+            for y in 0..N {
+                let (nx, ny) = f(x, y);
+                board.set(nx, ny, self.get(x, y));
+            }
+        }
+        board
+    }
+}
+
+impl<const M: usize, const N: usize, const K: usize> Game for MNKGame<M, N, K> {
+    type Action = (usize, usize); // x,y coordinates of the placed piece.
+    type Undo = (usize, usize);
+
+    fn new() -> Self {
+        MNKGame {
+            bitboards: [[0; WORDS]; 2],
+            hash: 0,
+            cur_player: Player::Red,
+            game_state: GameState::InProgress,
+            nb_moves: 0,
+        }
+    }
+
+    // Assumes that 'action' is a legal action.
+    fn play_action(&mut self, action: Action) -> Action {
+        assert_eq!(self.game_state, GameState::InProgress);
+        self.set(action.0, action.1, self.cur_player as u8);
+        self.nb_moves += 1;
+
+        if self.player_won([action.0, action.1]) {
+            self.game_state = GameState::Won(self.cur_player);
+        } else if self.is_full() {
+            self.game_state = GameState::Draw;
+        } else {
+            self.game_state = GameState::InProgress;
+        }
+        self.cur_player = !self.cur_player;
+        action
+    }
+
+    fn try_play_action(&mut self, action: Action) -> Result<Action, PlayError> {
+        if self.game_state != GameState::InProgress {
+            Err(PlayError::GameOver)
+        } else if action.0 >= M || action.1 >= N {
+            Err(PlayError::OutOfRange)
+        } else if self.get(action.0, action.1) != 0 {
+            Err(PlayError::Occupied)
+        } else {
+            Ok(self.play_action(action))
+        }
+    }
+
+    fn reverse_last_action(&mut self, last_action: Action) {
+        self.set(last_action.0, last_action.1, 0);
+        self.game_state = GameState::InProgress;
+        self.cur_player = !self.cur_player;
+        self.nb_moves -= 1;
+    }
+
+    fn game_state(&self) -> GameState {
+        self.game_state
+    }
+
+    fn cur_player(&self) -> Player {
+        self.cur_player
+    }
+
+    // the empty cells from the center and outwards, the search tries wins and blocks first itself.
+    fn generate_actions(&self, actions: &mut ActionBuffer<Action>) {
+        let start = actions.len();
+        for y in 0..N {
+            for x in 0..M {
+                if self.get(x, y) == 0 {
+                    actions.push((x, y));
+                }
+            }
+        }
+        // distances are doubled to stay integers on boards with an even side.
+        actions[start..].sort_by_key(|&(x, y)| {
+            let dx = (2*x as i32 - (M as i32-1)).abs();
+            let dy = (2*y as i32 - (N as i32-1)).abs();
+            (dx.max(dy), dx+dy)
+        });
+    }
+
+    fn is_winning_action(&self, action: Action, player: Player) -> bool {
+        self.longest_line([action.0, action.1], player as u8) >= K
+    }
+
+    fn vectorize(&self, player: Player) -> Vec<f64> {
+        let mut v = Vec::with_capacity(Self::CELLS);
+        // row by row starting in the bottom left corner.
+        for y in 0..N {
+            for x in 0..M {
+                let cur = self.get(x, y);
+                if cur == player as u8 {
+                    v.push(1.0);
+                } else if cur == !player as u8 {
+                    v.push(-1.0);
+                } else {
+                    v.push(0.0);
+                }
+            }
+        }
+        v
+    }
+
+    fn shape() -> [usize; 2] {
+        [M, N]
+    }
+
+    fn action_index((x, y): Action) -> usize {
+        x+y*M
+    }
+
+    fn index_to_action(index: usize) -> Action {
+        (index%M, index/M)
+    }
+
+    fn action_space() -> usize {
+        Self::CELLS
+    }
+
+    // all 8 rotations and reflections on square boards, rectangular boards only have 4.
+    fn symmetries(&self) -> Vec<Self> {
+        let mut symmetries = vec![
+            *self,
+            self.transform(|x, y| (M-1-x, y)),
+            self.transform(|x, y| (x, N-1-y)),
+            self.transform(|x, y| (M-1-x, N-1-y)),
+        ];
+        if M == N {
+            symmetries.push(self.transform(|x, y| (y, x)));
+            symmetries.push(self.transform(|x, y| (N-1-y, x)));
+            symmetries.push(self.transform(|x, y| (y, M-1-x)));
+            symmetries.push(self.transform(|x, y| (N-1-y, M-1-x)));
+        }
+        symmetries
+    }
+
+    // exact for boards with at most 64 cells. Larger boards use two independent Zobrist hashes, so
+    // two positions share a uid with a chance of about 2^-128, see Game::uid.
+    fn uid(&self) -> u128 {
+        if Self::CELLS <= 64 {
+            self.bitboards[0][0] as u128 | (self.bitboards[1][0] as u128) << 64
+        } else {
+            self.hash as u128 | (Self::zobrist(&self.bitboards, &UID_KEYS) as u128) << 64
+        }
+    }
+
+    fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    fn length(&self) -> u32 {
+        self.nb_moves
+    }
+}
+
+impl<const M: usize, const N: usize, const K: usize> fmt::Debug for MNKGame<M, N, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = String::new();
+        for y in (0..N).rev() {
+            for x in 0..M {
+                match self.get(x,y) {
+                    0 => s.push_str("# "),
+                    1 => {
+                        s.push_str("\x1b[30;41m \x1b[0m ");
+                    }
+                    _ => {
+                        s.push_str("\x1b[30;43m \x1b[0m ");
+                    }
+                }
+            }
+            s.push('\n');
+        }
+        write!(f, "{}", &s)
+    }
+}
+
+// Actions are written as "x,y".
+impl<const M: usize, const N: usize, const K: usize> ActionNotation for MNKGame<M, N, K> {
+    fn format_action((x, y): Action) -> String {
+        format!("{},{}", x, y)
+    }
+
+    fn parse_action(s: &str) -> Result<Action, NotationError> {
+        let invalid = || NotationError::InvalidAction(s.to_string());
+        let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+        Ok((x.parse().map_err(|_| invalid())?, y.parse().map_err(|_| invalid())?))
+    }
+}
+
+impl<const M: usize, const N: usize, const K: usize> PlayableGame for MNKGame<M, N, K> {
+    // returns (action, is_reverse)
+    fn get_action_from_user(&self) -> (Action, bool) {
+        let stdin = std::io::stdin();
+
+        fn parse_cord(s: &str) -> Option<(usize,usize)> {
+            let mut numbers = s.split(',');
+            let x = numbers.next()?.trim().parse::<usize>().ok()?;
+            let y = numbers.next()?.trim().parse::<usize>().ok()?;
+            Some((x,y))
+        }
+
+        for line in stdin.lock().lines() {
+            let line = line.unwrap();
+            if line.starts_with('z') {
+                return ((0,0), true);
+            } else if let Some((x,y)) = parse_cord(&line) {
+                if x < M && y < N {
+                    if self.get(x, y) != 0 {
+                        println!("Illegal action");
+                        continue;
+                    }
+                    return ((x,y), false);
+                } else {
+                    println!("Not in range (0..{}, 0..{})", M, N);
+                }
+            } else {
+                println!("Invalid input: try again");
+            }
+        }
+        panic!("Failed to get input from user");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluators::SimpleEval;
+    use crate::search::{search, SearchParams, TimeControl};
+
+    #[test]
+    fn wins() {
+        // diagonal
+        let mut board = TicTacToe::new();
+        for action in [(0, 0), (1, 0), (1, 1), (2, 0), (2, 2)] {
+            assert_eq!(board.game_state(), GameState::InProgress);
+            board.play_action(action);
+        }
+        assert_eq!(board.game_state(), GameState::Won(Player::Red));
+        board.reverse_last_action((2, 2));
+        assert_eq!(board.game_state(), GameState::InProgress);
+        assert_eq!(board.legal_actions().next(), Some((2, 2)));
+
+        // six in a row also wins in free-style Gomoku.
+        let mut board = FiveInARow::new();
+        for (x, y) in [(0, 0), (0, 8), (1, 0), (2, 8), (3, 0), (4, 8), (4, 0), (6, 8), (5, 0)] {
+            board.play_action((x, y));
+        }
+        // yellow has to block red.
+        assert!(!board.is_winning_action((2, 0), Player::Yellow));
+        assert_eq!(board.legal_actions().next(), Some((2, 0)));
+        board.play_action((8, 8));
+        assert_eq!(board.legal_actions().next(), Some((2, 0)));
+        board.play_action((2, 0));
+        assert_eq!(board.game_state(), GameState::Won(Player::Red));
+    }
+
+    #[test]
+    fn symmetries() {
+        let mut board = Gomoku::new();
+        for action in [(7, 7), (3, 9), (14, 0), (2, 2)] {
+            board.play_action(action);
+        }
+        let symmetries = board.symmetries();
+        assert_eq!(symmetries.len(), 8);
+        for (i, a) in symmetries.iter().enumerate() {
+            assert_eq!(a.hash(), Gomoku::zobrist(&a.bitboards, &ZOBRIST));
+            assert_eq!(a.get(7, 7), 1);
+            for b in &symmetries[i+1..] {
+                assert_ne!(a.uid(), b.uid());
+            }
+        }
+        assert_eq!(MNKGame::<5, 3, 3>::new().symmetries().len(), 4);
+        let v = board.vectorize(Player::Yellow);
+        assert_eq!(v.len(), 225);
+        assert_eq!(v[Gomoku::action_index((3, 9))], 1.0);
+        assert_eq!(Gomoku::index_to_action(Gomoku::action_index((3, 9))), (3, 9));
+    }
+
+    #[test]
+    fn tic_tac_toe_is_a_draw() {
+        let board = TicTacToe::new();
+        let result = search(&board, SearchParams::new(TimeControl::Depth(9)), 0, &SimpleEval::new(), Player::Red, None);
+        assert_eq!(result.score, 0.0);
+        assert_eq!(result.depth, 9);
+    }
+}
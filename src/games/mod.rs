@@ -1,39 +1,155 @@
 
 pub mod stack4;
 pub mod connect4;
+pub mod mnk;
+pub mod othello;
+pub mod notation;
 
 use num_derive::{FromPrimitive};
 use serde::{Serialize, Deserialize};
+use smallvec::SmallVec;
 use std::ops;
 use std::fmt;
 
 // A two player with three possible outcomes, win for either player or a draw.
-pub trait Game: Clone+Copy+fmt::Debug {
-    type Action: Copy+fmt::Debug;
+pub trait Game: Clone+Copy+fmt::Debug+Send+Sync {
+    type Action: Copy+fmt::Debug+PartialEq+Send+Sync+'static;
+    // Everything needed to take back an action, for example the pieces it captured.
+    type Undo: Copy+fmt::Debug+Send+Sync;
     
     fn new() -> Self;
-    fn play_action(&mut self, action: Self::Action);
-    fn reverse_last_action(&mut self, last_action: Self::Action);
+    // May panic or leave the board in a broken state if 'action' is not legal.
+    fn play_action(&mut self, action: Self::Action) -> Self::Undo;
+
+    // Same as play_action but checks that 'action' is legal first, the board is unchanged on error.
+    fn try_play_action(&mut self, action: Self::Action) -> Result<Self::Undo, PlayError> {
+        if self.game_state() != GameState::InProgress {
+            return Err(PlayError::GameOver);
+        }
+        if !self.legal_actions().any(|a| a == action) {
+            return Err(PlayError::Illegal);
+        }
+        Ok(self.play_action(action))
+    }
+    // 'undo' has to be returned by the last call to play_action, 
+    // several actions are taken back by reversing them in the opposite order.
+    fn reverse_last_action(&mut self, undo: Self::Undo);
     
     fn game_state(&self) -> GameState;
     fn cur_player(&self) -> Player;
 
-    fn legal_actions(&self) -> Box<dyn Iterator<Item=Self::Action>>;
+    // Adds the legal actions to 'actions' in a fixed order without looking at what they lead to.
+    // When the player to move has no other legal action the pass action is the only one.
+    fn generate_actions(&self, actions: &mut ActionBuffer<Self::Action>);
+
+    // The actions that win come first, then the ones that stop the opponent from winning
+    // next turn and then the rest in the order of generate_actions.
+    fn legal_actions(&self) -> Box<dyn Iterator<Item=Self::Action>> {
+        let mut actions = ActionBuffer::new();
+        self.generate_actions(&mut actions);
+        let player = self.cur_player();
+        let (wins, rest): (ActionBuffer<_>, ActionBuffer<_>) = actions.into_iter().partition(|&a| self.is_winning_action(a, player));
+        let (blocks, rest): (ActionBuffer<_>, ActionBuffer<_>) = rest.into_iter().partition(|&a| self.is_winning_action(a, !player));
+        Box::new(wins.into_iter().chain(blocks).chain(rest))
+    }
+
+    // Whether 'player' wins by playing the legal 'action', also when it is not the turn of 'player'.
+    // Only used to order actions, so games where it is expensive to tell always return false.
+    fn is_winning_action(&self, _action: Self::Action, _player: Player) -> bool {
+        false
+    }
+
+    // The action that passes the turn to the opponent, None in games where passing is never allowed.
+    fn pass_action() -> Option<Self::Action> {
+        None
+    }
+
+    // Margin of victory for 'player' when the game is over, for games where
+    // the outcome is more than win, loss or draw, for example discs in Othello.
+    fn final_score(&self, _player: Player) -> Option<i32> {
+        None
+    }
 
     fn vectorize(&self, player: Player) -> Vec<f64>;
     
     // Returns all states that are equal under symmetry including self.
     fn symmetries(&self) -> Vec<Self>;
 
+    // Identifies the position, equal positions have the same uid and different positions different uids.
+    // Positions that do not fit in 128 bits, the mnk boards with more than 64 cells, get a 128 bit hash
+    // instead, where two positions could get the same uid although it is very unlikely.
     fn uid(&self) -> u128;
 
+    // The smallest uid among the symmetries, the same for all positions that are equal under symmetry.
+    fn canonical_uid(&self) -> u128 {
+        self.symmetries().iter().map(|b| b.uid()).min().unwrap()
+    }
+
+    // Zobrist hash of the position, updated incrementally when actions are played and reversed.
+    fn hash(&self) -> u64;
+
     // How many moves has been played.
     fn length(&self) -> u32;
 
     fn shape() -> [usize; 2];
 
+    // Actions numbered in 0..Self::action_space(), used to store actions compactly.
+    fn action_index(action: Self::Action) -> usize;
+    fn index_to_action(index: usize) -> Self::Action;
+    fn action_space() -> usize;
+
+}
+
+// Holds the actions of a position, only boards with more than 64 cells need to allocate.
+pub type ActionBuffer<A> = SmallVec<[A; 64]>;
+
+// Pseudo random numbers for Zobrist hashing, one for every combination of player and cell.
+// The player to move is not hashed since it follows from the number of pieces on the board.
+pub const fn zobrist_keys<const N: usize>(seed: u64) -> [[u64; N]; 2] {
+    let mut keys = [[0; N]; 2];
+    let mut state = seed;
+    let mut i = 0;
+    while i < 2*N {
+        // splitmix64
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        keys[i/N][i%N] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
 }
 
+// Why an action could not be played.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PlayError {
+    GameOver,
+    OutOfRange,
+    ColumnFull,
+    Occupied,
+    // in Stack4 a piece has to be placed next to an edge or on top of another piece.
+    NotReachable,
+    // any other reason, for example an Othello move that does not flip any discs.
+    Illegal,
+}
+
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            PlayError::GameOver => "the game is already over",
+            PlayError::OutOfRange => "the action is outside of the board",
+            PlayError::ColumnFull => "the column is full",
+            PlayError::Occupied => "the cell is not empty",
+            PlayError::NotReachable => "the cell can not be reached from an edge",
+            PlayError::Illegal => "the action is not legal",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl std::error::Error for PlayError {}
+
 // in the boards these are represented by two bit numbers where Empty=0, Full(Red)=1, Full(Yellow)=2 
 #[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum TileStates {
@@ -0,0 +1,268 @@
+// Textual notation for actions and positions.
+//
+// A game is written as the sequence of actions played from the starting position,
+// in Connect4 the columns counted from 1 one after the other ("4453") and in Stack4
+// the coordinates of the pieces separated by spaces ("3,0 4,0 3,1").
+//
+// A position can also be written in a FEN-like form: the rows from the top to the
+// bottom separated by '/', where 'r' and 'y' are pieces and numbers count empty cells,
+// followed by the player to move. The position after "4" in Connect4 is "7/7/7/7/7/3r3 y".
+
+use crate::games::{Game, GameState, Player, PlayError};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum NotationError {
+    InvalidAction(String),
+    // the action at this index of a sequence could not be played.
+    IllegalAction(usize, PlayError),
+    InvalidPosition(String),
+    // a game record that can not be read, see the record module.
+    InvalidRecord(String),
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationError::InvalidAction(s) => write!(f, "invalid action '{}'", s),
+            NotationError::IllegalAction(i, err) => write!(f, "move {} can not be played: {}", i+1, err),
+            NotationError::InvalidPosition(s) => write!(f, "invalid position: {}", s),
+            NotationError::InvalidRecord(s) => write!(f, "invalid game record: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+pub trait ActionNotation: Game {
+    fn format_action(action: Self::Action) -> String;
+    // Only checks the syntax, whether the action can be played is up to try_play_action.
+    fn parse_action(s: &str) -> Result<Self::Action, NotationError>;
+
+    fn format_actions(actions: &[Self::Action]) -> String {
+        actions.iter().map(|&a| Self::format_action(a)).collect::<Vec<_>>().join(" ")
+    }
+
+    fn parse_actions(s: &str) -> Result<Vec<Self::Action>, NotationError> {
+        s.split_whitespace().map(Self::parse_action).collect()
+    }
+}
+
+// Plays the actions in 's' from the starting position.
+pub fn replay<G: ActionNotation>(s: &str) -> Result<G, NotationError> {
+    let mut board = G::new();
+    for (i, action) in G::parse_actions(s)?.into_iter().enumerate() {
+        board.try_play_action(action).map_err(|err| NotationError::IllegalAction(i, err))?;
+    }
+    Ok(board)
+}
+
+// Accepts both a sequence of actions and the FEN-like form, which is recognized by the '/' between rows.
+pub fn parse_position<G>(s: &str) -> Result<G, NotationError>
+    where G: ActionNotation+FromStr<Err=NotationError>
+{
+    if s.contains('/') {
+        s.parse()
+    } else {
+        replay(s)
+    }
+}
+
+// Writes the FEN-like form of a board that is 'w' wide and 'h' high, 'get' returns
+// 0 for an empty cell, 1 for red and 2 for yellow.
+pub fn write_grid(f: &mut fmt::Formatter<'_>, w: usize, h: usize, get: impl Fn(usize, usize) -> u8, player: Player) -> fmt::Result {
+    for y in (0..h).rev() {
+        let mut empty = 0;
+        for x in 0..w {
+            let cell = get(x, y);
+            if cell == 0 {
+                empty += 1;
+                continue;
+            }
+            if empty > 0 {
+                write!(f, "{}", empty)?;
+                empty = 0;
+            }
+            write!(f, "{}", if cell == 1 {'r'} else {'y'})?;
+        }
+        if empty > 0 {
+            write!(f, "{}", empty)?;
+        }
+        if y > 0 {
+            write!(f, "/")?;
+        }
+    }
+    write!(f, " {}", if player == Player::Red {'r'} else {'y'})
+}
+
+// Parses the FEN-like form into the cells column by column from the bottom,
+// so that cell (x, y) is at index x*h+y, and the player to move.
+pub fn parse_grid(s: &str, w: usize, h: usize) -> Result<(Vec<u8>, Player), NotationError> {
+    let invalid = |msg: String| NotationError::InvalidPosition(msg);
+    let mut parts = s.split_whitespace();
+    let rows: Vec<&str> = parts.next().unwrap_or("").split('/').collect();
+    let player = match parts.next() {
+        Some("r") => Player::Red,
+        Some("y") => Player::Yellow,
+        _ => return Err(invalid("expected 'r' or 'y' as the player to move".to_string())),
+    };
+    if parts.next().is_some() {
+        return Err(invalid("unexpected text after the player to move".to_string()));
+    }
+    if rows.len() != h {
+        return Err(invalid(format!("expected {} rows but got {}", h, rows.len())));
+    }
+    let mut cells = vec![0; w*h];
+    for (i, row) in rows.iter().enumerate() {
+        let y = h-1-i;
+        let mut x = 0;
+        let mut chars = row.chars().peekable();
+        while let Some(c) = chars.next() {
+            let n = match c {
+                'r' | 'y' => 1,
+                '0'..='9' => {
+                    let mut n = c.to_digit(10).unwrap() as usize;
+                    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                        n = 10*n+d as usize;
+                        chars.next();
+                    }
+                    n
+                },
+                _ => return Err(invalid(format!("unexpected '{}' in row {}", c, i+1))),
+            };
+            if n == 0 || x+n > w {
+                return Err(invalid(format!("row {} does not have {} cells", i+1, w)));
+            }
+            if c == 'r' || c == 'y' {
+                cells[x*h+y] = if c == 'r' {1} else {2};
+            }
+            x += n;
+        }
+        if x != w {
+            return Err(invalid(format!("row {} does not have {} cells", i+1, w)));
+        }
+    }
+    Ok((cells, player))
+}
+
+// Checks that the cells are a possible position with 'player' to move and returns the number of moves played.
+pub fn count_moves(cells: &[u8], player: Player) -> Result<u32, NotationError> {
+    let mut counts = [0, 0];
+    for &cell in cells {
+        match cell {
+            0 => {},
+            1 | 2 => counts[cell as usize-1] += 1,
+            _ => return Err(NotationError::InvalidPosition(format!("invalid cell {}", cell))),
+        }
+    }
+    // red always moves first.
+    let to_move = if counts[0] == counts[1] {Player::Red} else {Player::Yellow};
+    if counts[0] < counts[1] || counts[0] > counts[1]+1 || player != to_move {
+        return Err(NotationError::InvalidPosition("the number of pieces does not match the player to move".to_string()));
+    }
+    Ok(counts[0]+counts[1])
+}
+
+// The state of a position given who has a winning line, only the player who moved last can have won.
+pub fn position_state(red_won: bool, yellow_won: bool, full: bool, player: Player) -> Result<GameState, NotationError> {
+    let winner = match (red_won, yellow_won) {
+        (true, true) => return Err(NotationError::InvalidPosition("both players have won".to_string())),
+        (true, false) => Some(Player::Red),
+        (false, true) => Some(Player::Yellow),
+        (false, false) => None,
+    };
+    match winner {
+        Some(p) if p == player => Err(NotationError::InvalidPosition("the player to move has already won".to_string())),
+        Some(p) => Ok(GameState::Won(p)),
+        None if full => Ok(GameState::Draw),
+        None => Ok(GameState::InProgress),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::games::connect4::{Connect4, Connect4Board};
+    use crate::games::stack4::Stack4;
+
+    fn random_game<G: Game>(seed: u64) -> Vec<G::Action> {
+        fastrand::seed(seed);
+        let mut board = G::new();
+        let mut actions = Vec::new();
+        while board.game_state() == GameState::InProgress {
+            let legal: Vec<_> = board.legal_actions().collect();
+            let action = legal[fastrand::usize(0..legal.len())];
+            board.play_action(action);
+            actions.push(action);
+        }
+        actions
+    }
+
+    // every prefix of a random game survives both forms of the notation.
+    fn round_trip<G>(seed: u64)
+        where G: ActionNotation+FromStr<Err=NotationError>+fmt::Display
+    {
+        let actions = random_game::<G>(seed);
+        for n in 0..=actions.len() {
+            let s = G::format_actions(&actions[..n]);
+            assert_eq!(G::parse_actions(&s).unwrap(), &actions[..n]);
+            let board: G = replay(&s).unwrap();
+            let fen = board.to_string();
+            let parsed: G = parse_position(&fen).unwrap();
+            assert_eq!(parsed.uid(), board.uid());
+            assert_eq!(parsed.hash(), board.hash());
+            assert_eq!(parsed.game_state(), board.game_state());
+            assert_eq!(parsed.cur_player(), board.cur_player());
+            assert_eq!(parsed.length(), board.length());
+            assert_eq!(parsed.to_string(), fen);
+        }
+    }
+
+    #[test]
+    fn connect4() {
+        let board: Connect4 = parse_position("4453").unwrap();
+        assert_eq!(Connect4::format_actions(&[3, 3, 4, 2]), "4453");
+        assert_eq!(board.to_string(), "7/7/7/7/3y3/2yrr2 r");
+        assert_eq!(Connect4::new().to_string(), "7/7/7/7/7/7 r");
+        assert_eq!(parse_position::<Connect4>("").unwrap().uid(), Connect4::new().uid());
+        for seed in 0..10 {
+            round_trip::<Connect4>(seed);
+            round_trip::<Connect4Board<12, 4, 4>>(seed);
+        }
+    }
+
+    #[test]
+    fn stack4() {
+        let board: Stack4 = parse_position("3,0 0,7 3,1").unwrap();
+        assert_eq!(Stack4::format_actions(&[(3, 0), (0, 7), (3, 1)]), "3,0 0,7 3,1");
+        assert_eq!(board.to_string(), "y7/8/8/8/8/8/3r4/3r4 y");
+        for seed in 0..10 {
+            round_trip::<Stack4>(seed);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(replay::<Connect4>("44-").err(), Some(NotationError::InvalidAction("-".to_string())));
+        assert_eq!(replay::<Connect4>("448").err(), Some(NotationError::IllegalAction(2, PlayError::OutOfRange)));
+        assert_eq!(replay::<Connect4>("4444444").err(), Some(NotationError::IllegalAction(6, PlayError::ColumnFull)));
+        assert_eq!(replay::<Stack4>("3,0 3,0").err(), Some(NotationError::IllegalAction(1, PlayError::Occupied)));
+        assert!(replay::<Stack4>("3,0 3").is_err());
+        for fen in [
+            "7/7/7/7/7/7",          // no player to move
+            "7/7/7/7/7/3r3 r",      // wrong player to move
+            "7/7/7/7/3r3/7 y",      // floating piece
+            "7/7/7/7/7/3r2 y",      // short row
+            "7/7/7/7/7 r",          // missing row
+            "7/7/7/r6/r6/ryyyyrr y",  // the player to move has won
+        ] {
+            assert!(fen.parse::<Connect4>().is_err(), "{}", fen);
+        }
+        assert!("8/8/8/8/4r3/8/8/8 y".parse::<Stack4>().is_err());
+        let board: Connect4 = "7/7/7/r6/r6/ryyyr2 y".parse().unwrap();
+        assert_eq!(board.game_state(), GameState::InProgress);
+        let board: Connect4 = "7/7/7/r6/r6/ryyyyr1 r".parse().unwrap();
+        assert_eq!(board.game_state(), GameState::Won(Player::Yellow));
+    }
+}
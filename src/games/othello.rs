@@ -0,0 +1,546 @@
+use serde::{Serialize, Deserialize};
+use crate::games::{Player, GameState, PlayError};
+use crate::games::{ActionBuffer, Game, zobrist_keys};
+use crate::games::notation::{ActionNotation, NotationError};
+use crate::matchmaker::PlayableGame;
+use std::fmt;
+use std::io::BufRead;
+
+const BOARD_SIZE: usize = 8;
+// square index of the pass action.
+pub const PASS: usize = BOARD_SIZE*BOARD_SIZE;
+
+// indexed by player and then by the square.
+const ZOBRIST: [[u64; BOARD_SIZE*BOARD_SIZE]; 2] = zobrist_keys(0x07);
+// the player to move does not follow from the number of discs since players can pass.
+const ZOBRIST_YELLOW_TO_MOVE: u64 = zobrist_keys::<1>(0x08)[0][0];
+
+const NOT_A_FILE: u64 = 0xfefefefefefefefe;
+const NOT_H_FILE: u64 = 0x7f7f7f7f7f7f7f7f;
+// d4, one of the center squares which are never empty.
+const CENTER_SQUARE: usize = 3+3*BOARD_SIZE;
+
+// corners first and the squares next to the corners last.
+const SQUARE_ORDER: [usize; 64] = {
+    const WEIGHTS: [u8; 64] = [
+        0, 6, 2, 3, 3, 2, 6, 0,
+        6, 7, 5, 4, 4, 5, 7, 6,
+        2, 5, 1, 1, 1, 1, 5, 2,
+        3, 4, 1, 1, 1, 1, 4, 3,
+        3, 4, 1, 1, 1, 1, 4, 3,
+        2, 5, 1, 1, 1, 1, 5, 2,
+        6, 7, 5, 4, 4, 5, 7, 6,
+        0, 6, 2, 3, 3, 2, 6, 0,
+    ];
+    let mut order = [0; 64];
+    let mut n = 0;
+    let mut weight = 0;
+    while weight < 8 {
+        let mut square = 0;
+        while square < 64 {
+            if WEIGHTS[square] == weight {
+                order[n] = square;
+                n += 1;
+            }
+            square += 1;
+        }
+        weight += 1;
+    }
+    order
+};
+
+// A disc placed on 'square' = x+y*8, or PASS.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Action {
+    pub square: usize,
+}
+
+impl Action {
+    pub fn new(x: usize, y: usize) -> Action {
+        Action { square: x+y*BOARD_SIZE }
+    }
+
+    pub fn pass() -> Action {
+        Action { square: PASS }
+    }
+
+    pub fn is_pass(&self) -> bool {
+        self.square == PASS
+    }
+}
+
+// The action together with the discs it flipped.
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    pub action: Action,
+    pub flips: u64,
+}
+
+// written like a3 with the columns a-h and the rows 1-8.
+impl fmt::Debug for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_pass() {
+            write!(f, "pass")
+        } else {
+            let column = (b'a' + (self.square%BOARD_SIZE) as u8) as char;
+            write!(f, "{}{}", column, self.square/BOARD_SIZE+1)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Othello {
+    // one bitboard per player, bitboards[0] for red (black) and bitboards[1] for yellow (white).
+    // bit x+y*8 is set if the player has a disc at (x, y).
+    pub bitboards: [u64; 2],
+    pub hash: u64,
+    pub cur_player: Player,
+    pub game_state: GameState,
+    pub nb_moves: u32, // including passes.
+}
+
+// Moves the discs in a bitboard one step in the direction with index 'dir'.
+fn shift(bitboard: u64, dir: usize) -> u64 {
+    match dir {
+        0 => (bitboard << 1) & NOT_A_FILE, // east
+        1 => (bitboard >> 1) & NOT_H_FILE, // west
+        2 => bitboard << 8, // north
+        3 => bitboard >> 8, // south
+        4 => (bitboard << 9) & NOT_A_FILE, // north east
+        5 => (bitboard << 7) & NOT_H_FILE, // north west
+        6 => (bitboard >> 7) & NOT_A_FILE, // south east
+        _ => (bitboard >> 9) & NOT_H_FILE, // south west
+    }
+}
+
+impl Othello {
+    // Squares where 'own' can place a disc.
+    fn moves(own: u64, opponent: u64) -> u64 {
+        let empty = !(own | opponent);
+        let mut moves = 0;
+        for dir in 0..8 {
+            let mut x = shift(own, dir) & opponent;
+            for _ in 0..5 {
+                x |= shift(x, dir) & opponent;
+            }
+            moves |= shift(x, dir) & empty;
+        }
+        moves
+    }
+
+    // The opponent discs flipped when 'own' places a disc on 'square'.
+    fn flips(own: u64, opponent: u64, square: usize) -> u64 {
+        let mut flips = 0;
+        for dir in 0..8 {
+            let mut line = 0;
+            let mut x = shift(1 << square, dir);
+            while x & opponent != 0 {
+                line |= x;
+                x = shift(x, dir);
+            }
+            if x & own != 0 {
+                flips |= line;
+            }
+        }
+        flips
+    }
+
+    fn own_and_opponent(&self) -> (u64, u64) {
+        let p = self.cur_player as usize-1;
+        (self.bitboards[p], self.bitboards[1-p])
+    }
+
+    pub fn is_valid_move(&self, action: Action) -> bool {
+        let (own, opponent) = self.own_and_opponent();
+        let moves = Self::moves(own, opponent);
+        if action.is_pass() {
+            moves == 0
+        } else {
+            action.square < PASS && moves >> action.square & 1 != 0
+        }
+    }
+
+    // Number of discs of 'player'.
+    pub fn count(&self, player: Player) -> u32 {
+        self.bitboards[player as usize-1].count_ones()
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        let bit = 1 << (x+y*BOARD_SIZE);
+        if self.bitboards[0] & bit != 0 {
+            1
+        } else if self.bitboards[1] & bit != 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    // Computes the Zobrist hash from scratch.
+    fn zobrist(bitboards: &[u64; 2], cur_player: Player) -> u64 {
+        let mut hash = if cur_player == Player::Yellow { ZOBRIST_YELLOW_TO_MOVE } else { 0 };
+        for (keys, bitboard) in ZOBRIST.iter().zip(bitboards) {
+            let mut bitboard = *bitboard;
+            while bitboard != 0 {
+                hash ^= keys[bitboard.trailing_zeros() as usize];
+                bitboard &= bitboard-1;
+            }
+        }
+        hash
+    }
+
+    // Returns the board with the disc at square i moved to f(i).
+    fn transform(&self, f: impl Fn(usize, usize) -> (usize, usize)) -> Self {
+        let mut bitboards = [0; 2];
+        for (new, bitboard) in bitboards.iter_mut().zip(self.bitboards) {
+            for x in 0..BOARD_SIZE {
+                for y in 0..BOARD_SIZE {
+                    if bitboard >> (x+y*BOARD_SIZE) & 1 != 0 {
+                        let (nx, ny) = f(x, y);
+                        *new |= 1 << (nx+ny*BOARD_SIZE);
+                    }
+                }
+            }
+        }
+        Othello {
+            bitboards,
+            hash: Self::zobrist(&bitboards, self.cur_player),
+            ..*self
+        }
+    }
+}
+
+impl Game for Othello {
+    type Action = Action;
+    type Undo = Undo;
+
+    fn new() -> Self {
+        let bitboards = [
+            1 << (4+3*BOARD_SIZE) | 1 << (3+4*BOARD_SIZE),
+            1 << (3+3*BOARD_SIZE) | 1 << (4+4*BOARD_SIZE),
+        ];
+        Othello {
+            bitboards,
+            hash: Self::zobrist(&bitboards, Player::Red),
+            cur_player: Player::Red,
+            game_state: GameState::InProgress,
+            nb_moves: 0,
+        }
+    }
+
+    fn play_action(&mut self, action: Action) -> Undo {
+        assert_eq!(self.game_state, GameState::InProgress);
+        let p = self.cur_player as usize-1;
+        let mut undo = Undo { action, flips: 0 };
+        if !action.is_pass() {
+            let (own, opponent) = self.own_and_opponent();
+            let flips = Self::flips(own, opponent, action.square);
+            assert!(flips != 0, "illegal move {:?}", action);
+            undo.flips = flips;
+            self.bitboards[p] |= flips | 1 << action.square;
+            self.bitboards[1-p] &= !flips;
+            self.hash ^= ZOBRIST[p][action.square];
+            let mut flips = flips;
+            while flips != 0 {
+                let square = flips.trailing_zeros() as usize;
+                self.hash ^= ZOBRIST[p][square] ^ ZOBRIST[1-p][square];
+                flips &= flips-1;
+            }
+        }
+        self.nb_moves += 1;
+        self.cur_player = !self.cur_player;
+        self.hash ^= ZOBRIST_YELLOW_TO_MOVE;
+
+        // the game ends when neither player can move.
+        let (own, opponent) = self.own_and_opponent();
+        if Self::moves(own, opponent) == 0 && Self::moves(opponent, own) == 0 {
+            let (red, yellow) = (self.count(Player::Red), self.count(Player::Yellow));
+            self.game_state = if red > yellow {
+                GameState::Won(Player::Red)
+            } else if yellow > red {
+                GameState::Won(Player::Yellow)
+            } else {
+                GameState::Draw
+            };
+        }
+        undo
+    }
+
+    fn try_play_action(&mut self, action: Action) -> Result<Undo, PlayError> {
+        let (own, opponent) = self.own_and_opponent();
+        if self.game_state != GameState::InProgress {
+            Err(PlayError::GameOver)
+        } else if action.square > PASS {
+            Err(PlayError::OutOfRange)
+        } else if !action.is_pass() && (own | opponent) >> action.square & 1 != 0 {
+            Err(PlayError::Occupied)
+        } else if !self.is_valid_move(action) {
+            Err(PlayError::Illegal)
+        } else {
+            Ok(self.play_action(action))
+        }
+    }
+
+    fn reverse_last_action(&mut self, undo: Undo) {
+        self.cur_player = !self.cur_player;
+        self.hash ^= ZOBRIST_YELLOW_TO_MOVE;
+        self.nb_moves -= 1;
+        self.game_state = GameState::InProgress;
+        if undo.action.is_pass() {
+            return;
+        }
+        let p = self.cur_player as usize-1;
+        let flips = undo.flips;
+        let square = undo.action.square;
+        self.bitboards[p] &= !(flips | 1 << square);
+        self.bitboards[1-p] |= flips;
+        self.hash ^= ZOBRIST[p][square];
+        let mut flips = flips;
+        while flips != 0 {
+            let square = flips.trailing_zeros() as usize;
+            self.hash ^= ZOBRIST[p][square] ^ ZOBRIST[1-p][square];
+            flips &= flips-1;
+        }
+    }
+
+    fn game_state(&self) -> GameState {
+        self.game_state
+    }
+
+    fn cur_player(&self) -> Player {
+        self.cur_player
+    }
+
+    // corners first, a player without moves has to pass.
+    // is_winning_action is left to the default, a single disc rarely decides the game.
+    fn generate_actions(&self, actions: &mut ActionBuffer<Action>) {
+        let (own, opponent) = self.own_and_opponent();
+        let moves = Self::moves(own, opponent);
+        if moves == 0 {
+            actions.push(Action::pass());
+            return;
+        }
+        actions.extend(SQUARE_ORDER.iter()
+            .filter(|&&square| moves >> square & 1 != 0)
+            .map(|&square| Action { square }));
+    }
+
+    fn vectorize(&self, player: Player) -> Vec<f64> {
+        let own = self.bitboards[player as usize-1];
+        let opponent = self.bitboards[!player as usize-1];
+        // row by row starting in the bottom left corner.
+        (0..BOARD_SIZE*BOARD_SIZE).map(|square| {
+            if own >> square & 1 != 0 {
+                1.0
+            } else if opponent >> square & 1 != 0 {
+                -1.0
+            } else {
+                0.0
+            }
+        }).collect()
+    }
+
+    fn shape() -> [usize; 2] {
+        [BOARD_SIZE, BOARD_SIZE]
+    }
+
+    fn action_index(action: Action) -> usize {
+        action.square
+    }
+
+    fn index_to_action(index: usize) -> Action {
+        Action { square: index }
+    }
+
+    fn action_space() -> usize {
+        PASS+1
+    }
+
+    fn pass_action() -> Option<Action> {
+        Some(Action::pass())
+    }
+
+    // the difference in the number of discs.
+    fn final_score(&self, player: Player) -> Option<i32> {
+        match self.game_state {
+            GameState::InProgress => None,
+            _ => Some(self.count(player) as i32 - self.count(!player) as i32),
+        }
+    }
+
+    fn symmetries(&self) -> Vec<Self> {
+        let n = BOARD_SIZE-1;
+        vec![
+            *self,
+            self.transform(|x, y| (n-x, y)),
+            self.transform(|x, y| (x, n-y)),
+            self.transform(|x, y| (n-x, n-y)),
+            self.transform(|x, y| (y, x)),
+            self.transform(|x, y| (n-y, x)),
+            self.transform(|x, y| (y, n-x)),
+            self.transform(|x, y| (n-y, n-x)),
+        ]
+    }
+
+    // The center squares are never empty so the yellow bit of one of them is
+    // not needed and stores the player to move instead.
+    fn uid(&self) -> u128 {
+        let to_move = (self.cur_player == Player::Yellow) as u64;
+        let yellow = self.bitboards[1] & !(1 << CENTER_SQUARE) | to_move << CENTER_SQUARE;
+        self.bitboards[0] as u128 | (yellow as u128) << 64
+    }
+
+    fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    fn length(&self) -> u32 {
+        self.nb_moves
+    }
+}
+
+impl fmt::Debug for Othello {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = String::new();
+        let (own, opponent) = self.own_and_opponent();
+        let moves = Self::moves(own, opponent);
+        for y in (0..BOARD_SIZE).rev() {
+            s.push_str(&format!("{} ", y+1));
+            for x in 0..BOARD_SIZE {
+                match self.get(x,y) {
+                    0 => {
+                        if moves >> (x+y*BOARD_SIZE) & 1 != 0 {
+                            s.push_str("O ")
+                        } else {
+                            s.push_str("# ")
+                        }
+                    },
+                    1 => {
+                        s.push_str("\x1b[30;41m \x1b[0m ");
+                    }
+                    _ => {
+                        s.push_str("\x1b[30;43m \x1b[0m ");
+                    }
+                }
+            }
+            s.push('\n');
+        }
+        s.push_str("  a b c d e f g h\n");
+        write!(f, "{}", &s)
+    }
+}
+
+// The same as the Debug output of the actions, a square like d3 or pass.
+impl ActionNotation for Othello {
+    fn format_action(action: Action) -> String {
+        format!("{:?}", action)
+    }
+
+    fn parse_action(s: &str) -> Result<Action, NotationError> {
+        if s == "pass" {
+            return Ok(Action::pass());
+        }
+        let invalid = || NotationError::InvalidAction(s.to_string());
+        let mut chars = s.chars();
+        let x = chars.next().ok_or_else(invalid)? as usize;
+        let y = chars.next().and_then(|c| c.to_digit(10)).ok_or_else(invalid)? as usize;
+        if chars.next().is_some() || !(('a' as usize)..('a' as usize + BOARD_SIZE)).contains(&x) || !(1..=BOARD_SIZE).contains(&y) {
+            return Err(invalid());
+        }
+        Ok(Action::new(x-'a' as usize, y-1))
+    }
+}
+
+impl PlayableGame for Othello {
+    // returns (action, is_reverse)
+    fn get_action_from_user(&self) -> (Action, bool) {
+        let stdin = std::io::stdin();
+
+        for line in stdin.lock().lines() {
+            let line = line.unwrap();
+            if line.starts_with('z') {
+                return (Action::pass(), true);
+            } else if let Ok(action) = Othello::parse_action(line.trim()) {
+                match self.legal_actions().find(|a| *a == action) {
+                    Some(action) => return (action, false),
+                    None => println!("Illegal action"),
+                }
+            } else {
+                println!("Invalid input: write a square like d3 or pass");
+            }
+        }
+        panic!("Failed to get input from user");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perft(board: &mut Othello, depth: u32) -> u64 {
+        if depth == 0 || board.game_state() != GameState::InProgress {
+            return 1;
+        }
+        let mut nodes = 0;
+        for action in board.legal_actions() {
+            let before = *board;
+            let undo = board.play_action(action);
+            nodes += perft(board, depth-1);
+            board.reverse_last_action(undo);
+            assert_eq!(board.uid(), before.uid());
+            assert_eq!(board.hash(), before.hash());
+        }
+        nodes
+    }
+
+    #[test]
+    fn move_generation() {
+        let mut board = Othello::new();
+        let counts: Vec<u64> = (1..=6).map(|depth| perft(&mut board, depth)).collect();
+        assert_eq!(counts, vec![4, 12, 56, 244, 1396, 8200]);
+        let actions: Vec<Action> = board.legal_actions().collect();
+        assert_eq!(format!("{:?}", actions), "[d3, c4, f5, e6]");
+        board.play_action(Othello::index_to_action(3+2*8));
+        assert_eq!(board.count(Player::Red), 4);
+        assert_eq!(board.hash(), Othello::zobrist(&board.bitboards, Player::Yellow));
+    }
+
+    #[test]
+    fn random_games() {
+        fastrand::seed(3);
+        let mut passes = 0;
+        for _ in 0..100 {
+            let mut board = Othello::new();
+            let mut history = Vec::new();
+            while board.game_state() == GameState::InProgress {
+                let actions: Vec<Action> = board.legal_actions().collect();
+                let action = actions[fastrand::usize(0..actions.len())];
+                let before = board;
+                let undo = board.play_action(action);
+                if action.is_pass() {
+                    passes += 1;
+                    assert_eq!(Some(action), Othello::pass_action());
+                    assert_ne!(board.uid(), before.uid());
+                    assert_ne!(board.hash(), before.hash());
+                }
+                history.push((undo, board));
+            }
+            let (red, yellow) = (board.count(Player::Red), board.count(Player::Yellow));
+            assert_eq!(board.game_state() == GameState::Won(Player::Red), red > yellow);
+            assert_eq!(board.game_state() == GameState::Draw, red == yellow);
+            assert_eq!(board.final_score(Player::Yellow), Some(yellow as i32 - red as i32));
+            for symmetry in board.symmetries() {
+                assert_eq!(symmetry.count(Player::Red), red);
+                assert_eq!(symmetry.hash(), Othello::zobrist(&symmetry.bitboards, symmetry.cur_player));
+            }
+            // undo the whole game.
+            while let Some((undo, after)) = history.pop() {
+                assert_eq!(board.uid(), after.uid());
+                board.reverse_last_action(undo);
+            }
+            assert_eq!(board.uid(), Othello::new().uid());
+            assert_eq!(board.hash(), Othello::new().hash());
+        }
+        assert!(passes > 0);
+    }
+}
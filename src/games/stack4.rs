@@ -1,21 +1,26 @@
 
 use serde::{Serialize, Deserialize};
-use crate::games::{Player, GameState};
-use crate::games::Game;
+use crate::games::{Player, GameState, PlayError};
+use crate::games::{ActionBuffer, Game, zobrist_keys};
+use crate::games::notation::{self, ActionNotation, NotationError};
 use crate::matchmaker::PlayableGame;
 use std::fmt;
 use std::io::BufRead;
-use smallvec::SmallVec;
+use std::str::FromStr;
 
 const BOARD_SIZE: usize = 8;
 
 type Action = (usize, usize);
 
+// indexed by player and then by x+y*BOARD_SIZE.
+const ZOBRIST: [[u64; BOARD_SIZE*BOARD_SIZE]; 2] = zobrist_keys(0x54);
+
 #[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Stack4 {
     // tile on board takes up 2 bits, 0 for empty, 1 for red, 2 for yellow. 
     // starts in bottom left corner and goes row by row.
     pub board: u128,
+    pub hash: u64,
     pub cur_player: Player,
     pub game_state: GameState,
     pub nb_moves: u32,
@@ -56,12 +61,6 @@ impl Stack4 {
         false
     }
 
-    pub fn is_winning_action(&self, action: Action, player: Player) -> bool {
-        let mut resulting_board = self.clone();
-        resulting_board.play_action(action);
-        resulting_board.game_state == GameState::Won(player)
-    } 
-
     pub fn is_full(&self) -> bool {
         let mut yellow_mask: u128 = 2;
         for _ in 0..64 {
@@ -78,11 +77,25 @@ impl Stack4 {
         //self.legal_actions().count() == 0
     }
 
+    // True if the cell (x, y) can be reached by sliding a piece in from one of the edges.
+    pub fn is_reachable(&self, x: usize, y: usize) -> bool {
+        let filled = |x: usize, y: usize| self.get(x, y) != 0;
+        (0..x).all(|i| filled(i, y)) || (x+1..BOARD_SIZE).all(|i| filled(i, y))
+            || (0..y).all(|j| filled(x, j)) || (y+1..BOARD_SIZE).all(|j| filled(x, j))
+    }
+
     pub fn in_board(x: i32, y: i32) -> bool {
         x >= 0 && y >= 0 && x < BOARD_SIZE as i32 && y < BOARD_SIZE as i32
     }
 
     pub fn set(&mut self, x: usize, y: usize, v: u8) {
+        let old = self.get(x, y);
+        if old != 0 {
+            self.hash ^= ZOBRIST[old as usize-1][x+y*BOARD_SIZE];
+        }
+        if v != 0 {
+            self.hash ^= ZOBRIST[v as usize-1][x+y*BOARD_SIZE];
+        }
         let k = 2*(x+y*BOARD_SIZE);
         let mask = 3 << k;
         self.board = (self.board & (!mask)) + ((v as u128) << k);
@@ -92,67 +105,117 @@ impl Stack4 {
         3 & (self.board >> (2*(x+y*BOARD_SIZE))) as u8
     }
 
-    // Returns board rotated by 90*n degrees
-    fn rotation(&self, n: u32) -> Self {
-        let n = n%4;
-        let mut new_board = Stack4::new();
-        for x in 0..BOARD_SIZE as i32 {
-            for y in 0..BOARD_SIZE as i32 {
-                let (nx, ny) = Self::rotate(x, y, n);
-                new_board.set(nx as usize, ny as usize, self.get(x as usize, y as usize))
-            }
+    // Builds a position from the cells column by column, cell (x, y) is at index x*8+y.
+    pub fn from_cells(cells: &[u8], player: Player) -> Result<Self, NotationError> {
+        if cells.len() != BOARD_SIZE*BOARD_SIZE {
+            return Err(NotationError::InvalidPosition(format!("expected {} cells but got {}", BOARD_SIZE*BOARD_SIZE, cells.len())));
         }
-        Stack4 {
-            board: new_board.board,
-            cur_player: self.cur_player,
-            game_state: self.game_state,
-            nb_moves: self.nb_moves,
+        let mut board = Stack4::new();
+        board.nb_moves = notation::count_moves(cells, player)?;
+        board.cur_player = player;
+        for (i, &cell) in cells.iter().enumerate() {
+            board.set(i/BOARD_SIZE, i%BOARD_SIZE, cell);
+        }
+        // pieces are never removed, so every piece stays reachable after it is placed.
+        let pieces: Vec<_> = (0..BOARD_SIZE*BOARD_SIZE).map(|i| [i/BOARD_SIZE, i%BOARD_SIZE])
+            .filter(|&[x, y]| board.get(x, y) != 0).collect();
+        if let Some([x, y]) = pieces.iter().find(|&&[x, y]| !board.is_reachable(x, y)) {
+            return Err(NotationError::InvalidPosition(format!("the piece at {},{} can not be reached from an edge", x, y)));
         }
+        let won = |p: Player| pieces.iter().any(|&pos| board.get(pos[0], pos[1]) == p as u8 && board.player_won(pos));
+        board.game_state = notation::position_state(won(Player::Red), won(Player::Yellow), board.is_full(), player)?;
+        Ok(board)
     }
 
-    // rotates a point 90*n degrees around the center of the board.
-    // center is located at (3.5, 3.5)
-    fn rotate(x: i32, y: i32, n: u32) -> (i32, i32) {
-        let n = n%4;
-        match n {
-            0 => ( x  ,  y  ),
-            1 => (-y+7,  x  ),
-            2 => (-x+7, -y+7),
-            3 => ( y  , -x+7),
-            _ => { panic!("Impossible!")}
-        }
+    // Computes the Zobrist hash from scratch.
+    fn zobrist(board: u128) -> u64 {
+        (0..BOARD_SIZE*BOARD_SIZE).fold(0, |hash, i| {
+            match 3 & (board >> (2*i)) as usize {
+                0 => hash,
+                v => hash ^ ZOBRIST[v-1][i],
+            }
+        })
+    }
+
+    // Returns board rotated by 90*n degrees counterclockwise around the center of the board.
+    fn rotation(&self, n: u32) -> Self {
+        let board = match n%4 {
+            0 => self.board,
+            1 => mirror_rows(transpose(self.board)),
+            2 => mirror_rows(flip(self.board)),
+            _ => flip(transpose(self.board)),
+        };
+        self.with_board(board)
     }
 
     // mirrors board around the middle of the board.
-    // assumes BOARD_SIZE = 8. so if BOARD_WIDTH changes then so must this function
     pub fn mirror(&self) -> Self {
-        let mut col_mask: u128 = 0;
-        for _ in 0..BOARD_SIZE {
-            col_mask = (col_mask<<BOARD_SIZE*2)+3;
-        }
-        let mut new_board:u128 = 0;
-        for i in 0..BOARD_SIZE/2 {
-            new_board += (self.board&(col_mask<<(i*2)) ) << ((BOARD_SIZE/2 -i)*4-2);
-        }
-        for i in 0..BOARD_SIZE/2 {
-            new_board += (self.board&(col_mask<<( BOARD_SIZE/2 +i)*2)) >> (i)*4+2;
-        }
-        
+        self.with_board(mirror_rows(self.board))
+    }
+
+    fn with_board(&self, board: u128) -> Self {
         Stack4 {
-            board: new_board,
-            cur_player: self.cur_player,
-            game_state: self.game_state,
-            nb_moves: self.nb_moves,
+            board,
+            hash: Self::zobrist(board),
+            ..*self
         }
     }
 }
 
+// The symmetries move all cells of the board at once, they assume BOARD_SIZE = 8 so that every row
+// is 16 bits. The masks select every other group of 8, 4 and 2 bits.
+const BYTES: u128 = u128::MAX/0xffff*0x00ff;
+const NIBBLES: u128 = u128::MAX/0xff*0x0f;
+const PAIRS: u128 = u128::MAX/0xf*0x3;
+
+// Spreads every bit of a mask with one bit per cell to both bits of the cell.
+const fn spread(mask: u64) -> u128 {
+    let mut spread = 0;
+    let mut i = 0;
+    while i < 64 {
+        spread |= ((mask >> i & 1) as u128 * 3) << (2*i);
+        i += 1;
+    }
+    spread
+}
+
+// The masks and shifts of the usual transpose of an 8x8 bitboard with cells of 2 bits.
+const TRANSPOSE: [(u128, u32); 3] = [
+    (spread(0x0f0f0f0f00000000), 56),
+    (spread(0x3333000033330000), 28),
+    (spread(0x5500550055005500), 14),
+];
+
+// (x, y) moves to (7-x, y).
+fn mirror_rows(board: u128) -> u128 {
+    let board = (board >> 8) & BYTES | (board & BYTES) << 8;
+    let board = (board >> 4) & NIBBLES | (board & NIBBLES) << 4;
+    (board >> 2) & PAIRS | (board & PAIRS) << 2
+}
+
+// (x, y) moves to (x, 7-y).
+fn flip(board: u128) -> u128 {
+    let board = board.swap_bytes();
+    (board >> 8) & BYTES | (board & BYTES) << 8
+}
+
+// (x, y) moves to (y, x).
+fn transpose(mut board: u128) -> u128 {
+    for (mask, shift) in TRANSPOSE {
+        let t = mask & (board ^ (board << shift));
+        board ^= t ^ (t >> shift);
+    }
+    board
+}
+
 impl Game for Stack4 {
     type Action = (usize, usize); // x,y coordinates of the placed piece.
+    type Undo = (usize, usize);
 
     fn new() -> Self {
         Self {
             board: 0,
+            hash: 0,
             cur_player: Player::Red,
             game_state: GameState::InProgress,
             nb_moves: 0,
@@ -160,7 +223,7 @@ impl Game for Stack4 {
     }
 
     // Assumes that 'action' is a legal action.
-    fn play_action(&mut self, action: Self::Action) {
+    fn play_action(&mut self, action: Self::Action) -> Self::Undo {
         assert_eq!(self.game_state, GameState::InProgress);
         self.set(action.0, action.1, self.cur_player as u8);
         self.nb_moves += 1;
@@ -173,9 +236,25 @@ impl Game for Stack4 {
             self.game_state = GameState::InProgress;
         }
         self.cur_player = !self.cur_player;
+        action
     }
 
-    fn reverse_last_action(&mut self, last_action: Self::Action) {
+    fn try_play_action(&mut self, action: Self::Action) -> Result<Self::Undo, PlayError> {
+        let (x, y) = action;
+        if self.game_state != GameState::InProgress {
+            Err(PlayError::GameOver)
+        } else if x >= BOARD_SIZE || y >= BOARD_SIZE {
+            Err(PlayError::OutOfRange)
+        } else if self.get(x, y) != 0 {
+            Err(PlayError::Occupied)
+        } else if !self.is_reachable(x, y) {
+            Err(PlayError::NotReachable)
+        } else {
+            Ok(self.play_action(action))
+        }
+    }
+
+    fn reverse_last_action(&mut self, last_action: Self::Undo) {
         self.set(last_action.0, last_action.1, 0);
         self.game_state = GameState::InProgress;
         self.cur_player = !self.cur_player;
@@ -190,17 +269,13 @@ impl Game for Stack4 {
         self.cur_player
     }
 
-    fn legal_actions(&self) -> Box<dyn Iterator<Item=Action>> {
+    fn generate_actions(&self, actions: &mut ActionBuffer<Action>) {
         let dirs = [[1,0], [0,1], [-1,0], [0, -1]];
         let starts = [[0,0], [BOARD_SIZE-1, 0], [BOARD_SIZE-1, BOARD_SIZE-1], [0, BOARD_SIZE-1]];
         let mut prev_actions: u64 = 0;
-        let mut winning_moves = SmallVec::<[Action; BOARD_SIZE*4]>::new();
-
-        let mut blocking_moves = SmallVec::<[Action; BOARD_SIZE*4]>::new();
 
         let move_order = [3, 4, 2, 5, 1, 6, 0, 7];
 
-        let mut actions = SmallVec::<[Action; BOARD_SIZE*4]>::new();
         for c in move_order {
             for (dir, start) in dirs.iter().zip(starts) {
                 let inward_direction = [-dir[1], dir[0]];
@@ -213,13 +288,7 @@ impl Game for Stack4 {
                     // 0 represents TileStates::Empty
                     if self.get(cur_cord[0], cur_cord[1]) == 0 {
                         if prev_actions>>(cur_cord[0]+cur_cord[1]*BOARD_SIZE)&1==0 {
-                            if self.is_winning_action((cur_cord[0], cur_cord[1]), self.cur_player) {
-                                winning_moves.push((cur_cord[0], cur_cord[1]))
-                            } else if self.is_winning_action((cur_cord[0], cur_cord[1]), !self.cur_player) {
-                                blocking_moves.push((cur_cord[0], cur_cord[1]))
-                            } else {
-                                actions.push((cur_cord[0], cur_cord[1]));
-                            }
+                            actions.push((cur_cord[0], cur_cord[1]));
                             prev_actions += 1<<(cur_cord[0]+cur_cord[1]*BOARD_SIZE);
                         }
                         break
@@ -227,7 +296,13 @@ impl Game for Stack4 {
                 }
             }
         }
-        Box::new(winning_moves.into_iter().chain(blocking_moves.into_iter()).chain(actions.into_iter()))
+    }
+
+    // the piece is put on a copy of the board without playing the action, so it works for both players.
+    fn is_winning_action(&self, action: Action, player: Player) -> bool {
+        let mut board = *self;
+        board.board |= (player as u128) << (2*(action.0+action.1*BOARD_SIZE));
+        board.player_won([action.0, action.1])
     }
 
     fn vectorize(&self, player: Player) -> Vec<f64> {
@@ -253,6 +328,18 @@ impl Game for Stack4 {
         [BOARD_SIZE, BOARD_SIZE]
     }
 
+    fn action_index((x, y): Action) -> usize {
+        x+y*BOARD_SIZE
+    }
+
+    fn index_to_action(index: usize) -> Action {
+        (index%BOARD_SIZE, index/BOARD_SIZE)
+    }
+
+    fn action_space() -> usize {
+        BOARD_SIZE*BOARD_SIZE
+    }
+
     fn symmetries(&self) -> Vec<Self> {
         let mut symmetries = Vec::with_capacity(8);
         for n in 0..4 {
@@ -267,6 +354,16 @@ impl Game for Stack4 {
         self.board
     }
 
+    fn canonical_uid(&self) -> u128 {
+        let mirrored = mirror_rows(self.board);
+        [self.board, mirrored, flip(self.board), flip(mirrored)].into_iter()
+            .flat_map(|board| [board, transpose(board)]).min().unwrap()
+    }
+
+    fn hash(&self) -> u64 {
+        self.hash
+    }
+
     fn length(&self) -> u32 {
         self.nb_moves
     }
@@ -300,6 +397,35 @@ impl fmt::Debug for Stack4 {
     }
 }
 
+// The FEN-like form of the position, see the notation module.
+impl fmt::Display for Stack4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        notation::write_grid(f, BOARD_SIZE, BOARD_SIZE, |x, y| self.get(x, y), self.cur_player)
+    }
+}
+
+impl FromStr for Stack4 {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<Self, NotationError> {
+        let (cells, player) = notation::parse_grid(s, BOARD_SIZE, BOARD_SIZE)?;
+        Stack4::from_cells(&cells, player)
+    }
+}
+
+// Actions are written as "x,y", the same as when playing against the computer.
+impl ActionNotation for Stack4 {
+    fn format_action((x, y): Action) -> String {
+        format!("{},{}", x, y)
+    }
+
+    fn parse_action(s: &str) -> Result<Action, NotationError> {
+        let invalid = || NotationError::InvalidAction(s.to_string());
+        let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+        Ok((x.parse().map_err(|_| invalid())?, y.parse().map_err(|_| invalid())?))
+    }
+}
+
 impl PlayableGame for Stack4 {
     // returns (action, is_reverse)
     fn get_action_from_user(&self) -> (Action, bool) {
@@ -315,7 +441,7 @@ impl PlayableGame for Stack4 {
 
         for line in stdin.lock().lines() {
             let line = line.unwrap();
-            if line.as_bytes()[0] == 'z' as u8 {
+            if line.starts_with('z') {
                 return ((0,0), true);
             } else if let Some((x,y)) = parse_cord(&line) {
                 if x < BOARD_SIZE && y < BOARD_SIZE {
@@ -338,7 +464,7 @@ impl PlayableGame for Stack4 {
 #[cfg(test)]
 mod tests {
     use super::Stack4;
-    use crate::games::{Game, GameState};
+    use crate::games::{ActionBuffer, Game, GameState, Player, PlayError};
     #[test]
     fn draw() {
         let actions = vec![(3, 0), (3, 1), (0, 2), (1, 0), (2, 0), (2, 1), (2, 2), (2, 3), (2, 4), (2, 5), (2, 6), (2, 7), (0, 0), (0, 1), (0, 3), (0, 4), (0, 5), (0, 6), (0, 7), (7, 7), (1, 1), (1, 2), (1, 3), (1, 4), (1, 5), (1, 6), (1, 7), (7, 6), (3, 2), (3, 3), (3, 4), (3, 5), (3, 6), (3, 7), (7, 5), (4, 0), (4, 1), (4, 2), (4, 3), (4, 4), (4, 5), (4, 6), (4, 7), (5, 0), (5, 1), (5, 2), (5, 3), (5, 4), (5, 5), (5, 6), (5, 7), (6, 7), (6, 0), (6, 1), (6, 2), (6, 3), (6, 4), (6, 5), (6, 6), (7, 0), (7, 1), (7, 2), (7, 3), (7, 4)];
@@ -362,4 +488,88 @@ mod tests {
         assert!(board.is_full());
         assert_ne!(board.game_state(), GameState::InProgress);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn zobrist_hash() {
+        let mut board = Stack4::new();
+        for action in [(3, 0), (0, 3), (3, 1), (7, 7), (4, 0)] {
+            board.play_action(action);
+        }
+        assert_eq!(board.hash(), Stack4::zobrist(board.board));
+        for symmetry in board.symmetries() {
+            assert_eq!(symmetry.hash(), Stack4::zobrist(symmetry.board));
+        }
+        let hash = board.hash();
+        let undo = board.play_action((0, 0));
+        assert_ne!(board.hash(), hash);
+        board.reverse_last_action(undo);
+        assert_eq!(board.hash(), hash);
+    }
+
+    #[test]
+    fn symmetries() {
+        fastrand::seed(7);
+        let mut board = Stack4::new();
+        while board.game_state() == GameState::InProgress {
+            let actions: Vec<_> = board.legal_actions().collect();
+            board.play_action(actions[fastrand::usize(0..actions.len())]);
+            let rotated = board.rotation(1);
+            let mirrored = board.mirror();
+            for x in 0..8 {
+                for y in 0..8 {
+                    assert_eq!(rotated.get(7-y, x), board.get(x, y));
+                    assert_eq!(mirrored.get(7-x, y), board.get(x, y));
+                }
+            }
+            let symmetries = board.symmetries();
+            let min = symmetries.iter().map(|b| b.uid()).min().unwrap();
+            for symmetry in &symmetries {
+                assert_eq!(symmetry.canonical_uid(), min);
+            }
+        }
+        assert_eq!(board.rotation(2).uid(), board.rotation(1).rotation(1).uid());
+        assert_eq!(board.rotation(3).uid(), board.rotation(2).rotation(1).uid());
+    }
+
+    #[test]
+    fn move_ordering() {
+        let mut board = Stack4::new();
+        for action in [(0, 0), (7, 0), (0, 1), (7, 1), (0, 2)] {
+            board.play_action(action);
+        }
+        // yellow has to block red, who is not the player to move.
+        assert!(board.is_winning_action((0, 3), Player::Red));
+        assert!(!board.is_winning_action((0, 3), Player::Yellow));
+        assert_eq!(board.legal_actions().next(), Some((0, 3)));
+        board.play_action((7, 2));
+        let ordered: Vec<_> = board.legal_actions().collect();
+        assert_eq!(ordered[..2], [(0, 3), (7, 3)]);
+        let mut generated = ActionBuffer::new();
+        board.generate_actions(&mut generated);
+        assert_eq!(generated.len(), ordered.len());
+        assert!(generated.iter().all(|a| ordered.contains(a)));
+    }
+
+    #[test]
+    fn try_play_action() {
+        let mut board = Stack4::new();
+        assert_eq!(board.try_play_action((8, 0)), Err(PlayError::OutOfRange));
+        assert_eq!(board.try_play_action((3, 3)), Err(PlayError::NotReachable));
+        assert_eq!(board.try_play_action((0, 3)), Ok((0, 3)));
+        assert_eq!(board.try_play_action((0, 3)), Err(PlayError::Occupied));
+        // next to the piece on the left edge.
+        assert_eq!(board.try_play_action((1, 3)), Ok((1, 3)));
+        for action in [(2, 3), (7, 7), (3, 3)] {
+            board.play_action(action);
+        }
+        let copy = board;
+        assert_eq!(board.try_play_action((6, 6)), Err(PlayError::NotReachable));
+        assert_eq!(board.uid(), copy.uid());
+        assert_eq!(board.try_play_action((7, 6)), Ok((7, 6)));
+        // yellow gets four in a row down from the top right corner.
+        for action in [(0, 0), (7, 5), (0, 1), (7, 4)] {
+            board.play_action(action);
+        }
+        assert_eq!(board.try_play_action((0, 2)), Err(PlayError::GameOver));
+    }
+}
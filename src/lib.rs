@@ -1,7 +1,13 @@
 pub mod matchmaker;
+pub mod record;
+pub mod book;
+pub mod endgame;
 pub mod evaluators;
 pub mod games;
 pub mod search;
+pub mod mcts;
+pub mod alphazero;
+pub mod solver;
 pub mod qlearning;
 pub mod policies;
 pub mod agents;
@@ -1,6 +1,8 @@
 
 use crate::games::{Player, GameState, Game};
+use crate::games::notation::ActionNotation;
 use crate::agents::Agent;
+use crate::record::{GameRecord, write_records};
 use std::fmt;
 
 pub trait PlayableGame: fmt::Debug+Game {
@@ -8,9 +10,12 @@ pub trait PlayableGame: fmt::Debug+Game {
     fn get_action_from_user(&self) -> (Self::Action, bool);
 }
 
-pub struct MatchMaker<'a, G> {
+pub struct MatchMaker<'a, G: Game> {
     agents: Vec<&'a dyn Agent<G>>, // Currently only works with 2 agents.
     hist: Vec<(usize, usize, GameState)>, // (idx of red agent, idx of yellow agent, result)
+    records: Vec<GameRecord<G>>,
+    // lets the agents analyse every move so that the records include the scores.
+    pub evaluations: bool,
 }
 
 impl<'a, G> MatchMaker<'a, G>
@@ -22,6 +27,8 @@ impl<'a, G> MatchMaker<'a, G>
         MatchMaker {
             agents: Vec::new(),
             hist: Vec::new(),
+            records: Vec::new(),
+            evaluations: false,
         }
     }
 
@@ -42,12 +49,17 @@ impl<'a, G> MatchMaker<'a, G>
             } else {
                 [1,0]
             };
-            let end_board = play_game(&*self.agents[idxagents[0]], &*self.agents[idxagents[1]]);
-            
-            self.hist.push((idxagents[0], idxagents[1], end_board.last().unwrap().game_state()));
+            let record = play_recorded_game(&*self.agents[idxagents[0]], &*self.agents[idxagents[1]], self.evaluations);
+            self.hist.push((idxagents[0], idxagents[1], record.result));
+            self.records.push(record);
         }
     }
 
+    // every game played so far, in order.
+    pub fn records(&self) -> &[GameRecord<G>] {
+        &self.records
+    }
+
     pub fn scores(&self) -> Vec<[i32;3]> {
         let mut scores = vec![[0;3];self.agents.len()]; // Vec of [draws, wins, losses]
         let mut nbgames = vec![0;self.agents.len()];
@@ -135,9 +147,15 @@ impl<'a, G> MatchMaker<'a, G>
     }
 }
 
+impl<'a, G: ActionNotation> MatchMaker<'a, G> {
+    pub fn save_records(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, write_records(&self.records))
+    }
+}
+
 pub fn user_vs_user<G: PlayableGame>() {
     let mut board = G::new();
-    let mut last_action: Option<G::Action> = None;
+    let mut undos = Vec::new();
     let mut actions = Vec::new();
     loop {
         println!("{:?}", board);
@@ -145,12 +163,18 @@ pub fn user_vs_user<G: PlayableGame>() {
         println!("{:?}", board.game_state());
         let (action, reverse) = board.get_action_from_user();
         if reverse {
-            if let Some(last_action) = last_action {
-                board.reverse_last_action(last_action);
+            if let Some(undo) = undos.pop() {
+                board.reverse_last_action(undo);
+                actions.pop();
             }
         } else {
-            last_action = Some(action);
-            board.play_action(action);
+            match board.try_play_action(action) {
+                Ok(undo) => undos.push(undo),
+                Err(err) => {
+                    println!("{}", err);
+                    continue
+                }
+            }
             actions.push(action);
             match board.game_state() {
                 GameState::Draw => {
@@ -176,31 +200,43 @@ pub fn user_vs_agent<G, A>(opponent: &A)
     let mut board = G::new();
     let p = board.cur_player();
 
-    let mut actions = Vec::new();
+    let mut undos = Vec::new();
 
     loop {
         println!("{:?}", board);
         let (action, reverse) = board.get_action_from_user();
         if reverse {
-            board.reverse_last_action(actions[actions.len()-1]);
-            board.reverse_last_action(actions[actions.len()-2]);
-            actions.remove(actions.len()-1);
-            actions.remove(actions.len()-1);
+            // takes back the last move of both the agent and the user.
+            if undos.len() >= 2 {
+                for undo in undos.drain(undos.len()-2..).rev() {
+                    board.reverse_last_action(undo);
+                }
+            }
             continue
         } else {
-            actions.push(action);
-            board.play_action(action);
+            match board.try_play_action(action) {
+                Ok(undo) => undos.push(undo),
+                Err(err) => {
+                    println!("{}", err);
+                    continue
+                }
+            }
             println!("{:?}", board);
             if board.game_state() != GameState::InProgress {
                 break
             }                
         }
-        let action = opponent.get_action(&board, !p);
-        unsafe {
-            crate::search::LEAF_COUNT = 0;
-        }
-        actions.push(action);
-        board.play_action(action);
+        let action = match opponent.analyse(&board, !p) {
+            Some(result) => {
+                println!("score: {}, depth: {}", result.score, result.depth);
+                println!("{}", result.stats);
+                println!("pv: {:?}", result.pv);
+                println!("actions: {:?}", result.root_scores);
+                result.best_action
+            },
+            None => opponent.get_action(&board, !p),
+        };
+        undos.push(board.play_action(action));
         if board.game_state() != GameState::InProgress {
             break
         }
@@ -215,6 +251,9 @@ pub fn user_vs_agent<G, A>(opponent: &A)
             println!("{:?} won", player);   
         }
     }
+    if let Some(score) = board.final_score(p) {
+        println!("final score: {}", score);
+    }
 }
 
 fn movmean(v: &Vec<f64>, n: usize) -> Vec<f64> {
@@ -255,4 +294,27 @@ pub fn play_game<G: Game>(p1: &dyn Agent<G>, p2: &dyn Agent<G>) -> Vec<G>
         boards.push(board.clone());
     }
     boards
-}
\ No newline at end of file
+}
+
+// Same as play_game but keeps the actions in a record, with 'evaluations' the agents
+// analyse every move and the scores of their searches are recorded as well.
+pub fn play_recorded_game<G: Game>(p1: &dyn Agent<G>, p2: &dyn Agent<G>, evaluations: bool) -> GameRecord<G> {
+    let mut record = GameRecord::new(p1.description(), p2.description());
+    let mut board = G::new();
+    while board.game_state() == GameState::InProgress {
+        let agent = if board.cur_player() == Player::Red {p1} else {p2};
+        let result = if evaluations {
+            agent.analyse(&board, board.cur_player())
+        } else {
+            None
+        };
+        let (action, score) = match result {
+            Some(result) => (result.best_action, Some(result.score)),
+            None => (agent.get_action(&board, board.cur_player()), None),
+        };
+        board.play_action(action);
+        record.push(action, score);
+    }
+    record.finish(board.game_state());
+    record
+}
@@ -0,0 +1,334 @@
+use crate::agents::Agent;
+use crate::evaluators::Evaluator;
+use crate::games::{Player, GameState, Game};
+use crate::search::{SearchResult, SearchStats};
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+// How much effort is spent on every move.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MctsBudget {
+    Iterations(u32),
+    Time(Duration),
+}
+
+// How a newly expanded position is played out to the end of the game.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Rollout {
+    Random,
+    // plays the action that the evaluator likes the most,
+    // with probability epsilon a random action is played instead.
+    Greedy(f64),
+}
+
+struct Node<A> {
+    action: Option<A>, // the action that lead to this node, None for the root.
+    mover: Player, // the player who played 'action'.
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<A>,
+    visits: u32,
+    reward: f64, // sum of the rewards for 'mover', 1 for a win, 0.5 for a draw and 0 for a loss.
+}
+
+// The search tree, stored in an arena where the root is at index 0.
+struct Tree<G: Game> {
+    board: G, // the position at the root.
+    nodes: Vec<Node<G::Action>>,
+}
+
+impl<G: Game> Tree<G> {
+    fn new(board: &G) -> Tree<G> {
+        Tree {
+            board: *board,
+            nodes: vec![Tree::node(board, None, !board.cur_player(), None)],
+        }
+    }
+
+    fn node(board: &G, action: Option<G::Action>, mover: Player, parent: Option<usize>) -> Node<G::Action> {
+        let untried = if board.game_state() == GameState::InProgress {
+            board.legal_actions().collect()
+        } else {
+            Vec::new()
+        };
+        Node {
+            action,
+            mover,
+            parent,
+            children: Vec::new(),
+            untried,
+            visits: 0,
+            reward: 0.0,
+        }
+    }
+
+    // Looks for 'board' among the positions one or two moves below the root and makes it the new root.
+    fn reroot(mut self, board: &G) -> Option<Tree<G>> {
+        let uid = board.uid();
+        let mut stack = vec![(0, self.board, 0)];
+        while let Some((i, b, depth)) = stack.pop() {
+            if b.uid() == uid {
+                if i == 0 {
+                    return Some(self);
+                }
+                let mut tree = Tree { board: b, nodes: Vec::new() };
+                tree.copy_subtree(&mut self.nodes, i, None);
+                return Some(tree);
+            }
+            if depth < 2 {
+                for &c in &self.nodes[i].children {
+                    let mut child = b;
+                    child.play_action(self.nodes[c].action.unwrap());
+                    stack.push((c, child, depth+1));
+                }
+            }
+        }
+        None
+    }
+
+    fn copy_subtree(&mut self, nodes: &mut Vec<Node<G::Action>>, i: usize, parent: Option<usize>) {
+        let j = self.nodes.len();
+        let children = std::mem::take(&mut nodes[i].children);
+        let node = &mut nodes[i];
+        self.nodes.push(Node {
+            action: node.action,
+            mover: node.mover,
+            parent,
+            children: Vec::with_capacity(children.len()),
+            untried: std::mem::take(&mut node.untried),
+            visits: node.visits,
+            reward: node.reward,
+        });
+        for c in children {
+            let k = self.nodes.len();
+            self.nodes[j].children.push(k);
+            self.copy_subtree(nodes, c, Some(j));
+        }
+    }
+
+    // Upper confidence bound of child 'i' as seen by the player choosing among the children of its parent.
+    fn uct(&self, i: usize, ln_parent_visits: f64, exploration: f64) -> f64 {
+        let node = &self.nodes[i];
+        node.reward/node.visits as f64 + exploration*(ln_parent_visits/node.visits as f64).sqrt()
+    }
+
+    fn most_visited_child(&self, i: usize) -> Option<usize> {
+        self.nodes[i].children.iter().copied().max_by_key(|&c| self.nodes[c].visits)
+    }
+}
+
+// Monte Carlo tree search with UCT selection. The tree of the previous move is kept
+// and reused if the next position is found in it.
+pub struct MctsAgent<'a, G: Game, E> {
+    evaluator: &'a E,
+    pub budget: MctsBudget,
+    pub rollout: Rollout,
+    pub exploration: f64,
+    tree: RefCell<Option<Tree<G>>>,
+}
+
+impl<'a, G: Game, E: Evaluator<G>> MctsAgent<'a, G, E> {
+    pub fn new(evaluator: &'a E, budget: MctsBudget, rollout: Rollout) -> Self {
+        MctsAgent {
+            evaluator,
+            budget,
+            rollout,
+            exploration: 2f64.sqrt(),
+            tree: RefCell::new(None),
+        }
+    }
+
+    fn search(&self, board: &G) -> SearchResult<G::Action> {
+        let start = Instant::now();
+        let mut tree = self.tree.borrow_mut().take()
+            .and_then(|tree| tree.reroot(board))
+            .unwrap_or_else(|| Tree::new(board));
+        let mut stats = SearchStats::default();
+        let mut iterations = 0;
+        loop {
+            let done = match self.budget {
+                MctsBudget::Iterations(n) => iterations >= n,
+                MctsBudget::Time(duration) => iterations > 0 && start.elapsed() >= duration,
+            };
+            if done {
+                break;
+            }
+            self.iteration(&mut tree, &mut stats);
+            iterations += 1;
+        }
+
+        let best = tree.most_visited_child(0).expect("no legal actions");
+        let mut pv = Vec::new();
+        let mut i = best;
+        loop {
+            pv.push(tree.nodes[i].action.unwrap());
+            match tree.most_visited_child(i) {
+                Some(c) => i = c,
+                None => break,
+            }
+        }
+        let q = |i: usize| 2.0*tree.nodes[i].reward/tree.nodes[i].visits as f64 - 1.0;
+        stats.elapsed = start.elapsed();
+        let result = SearchResult {
+            best_action: tree.nodes[best].action.unwrap(),
+            score: q(best),
+            depth: pv.len() as u32,
+            pv,
+            root_scores: tree.nodes[0].children.iter().map(|&c| (tree.nodes[c].action.unwrap(), q(c))).collect(),
+            stats,
+        };
+        *self.tree.borrow_mut() = Some(tree);
+        result
+    }
+
+    // selection, expansion, rollout and backpropagation.
+    fn iteration(&self, tree: &mut Tree<G>, stats: &mut SearchStats) {
+        let mut board = tree.board;
+        let mut i = 0;
+        while tree.nodes[i].untried.is_empty() && !tree.nodes[i].children.is_empty() {
+            let ln_visits = (tree.nodes[i].visits as f64).ln();
+            i = *tree.nodes[i].children.iter().max_by(|&&a, &&b| {
+                tree.uct(a, ln_visits, self.exploration).partial_cmp(&tree.uct(b, ln_visits, self.exploration)).unwrap()
+            }).unwrap();
+            board.play_action(tree.nodes[i].action.unwrap());
+        }
+        if !tree.nodes[i].untried.is_empty() {
+            let untried = &mut tree.nodes[i].untried;
+            let action = untried.swap_remove(fastrand::usize(0..untried.len()));
+            let mover = board.cur_player();
+            board.play_action(action);
+            let child = tree.nodes.len();
+            tree.nodes.push(Tree::node(&board, Some(action), mover, Some(i)));
+            tree.nodes[i].children.push(child);
+            i = child;
+        }
+        stats.nodes += 1;
+
+        let result = self.play_out(board, stats);
+        let mut node = Some(i);
+        while let Some(j) = node {
+            let n = &mut tree.nodes[j];
+            n.visits += 1;
+            n.reward += match result {
+                GameState::Won(p) if p == n.mover => 1.0,
+                GameState::Won(_) => 0.0,
+                _ => 0.5,
+            };
+            node = n.parent;
+        }
+    }
+
+    fn play_out(&self, mut board: G, stats: &mut SearchStats) -> GameState {
+        while board.game_state() == GameState::InProgress {
+            let actions: Vec<G::Action> = board.legal_actions().collect();
+            let action = match self.rollout {
+                Rollout::Greedy(epsilon) if fastrand::f64() >= epsilon => {
+                    let player = board.cur_player();
+                    let mut best = (actions[0], -1./0.);
+                    for &action in &actions {
+                        let undo = board.play_action(action);
+                        let v = self.evaluator.value(&board, player);
+                        board.reverse_last_action(undo);
+                        stats.leaves += 1;
+                        if v > best.1 {
+                            best = (action, v);
+                        }
+                    }
+                    best.0
+                },
+                _ => actions[fastrand::usize(0..actions.len())],
+            };
+            board.play_action(action);
+        }
+        board.game_state()
+    }
+}
+
+impl<'a, G: Game, E: Evaluator<G>> Agent<G> for MctsAgent<'a, G, E> {
+    fn get_action(&self, board: &G, _player: Player) -> G::Action {
+        self.search(board).best_action
+    }
+
+    // the scores are the expected outcomes, between -1 for a loss and 1 for a win.
+    fn analyse(&self, board: &G, _player: Player) -> Option<SearchResult<G::Action>> {
+        Some(self.search(board))
+    }
+
+    fn description(&self) -> String {
+        format!("mcts {:?}", self.budget)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::agents::MinimaxAgent;
+    use crate::evaluators::{ConsequtiveEval, SimpleEval};
+    use crate::games::connect4::Connect4;
+    use crate::games::stack4::Stack4;
+    use crate::matchmaker::MatchMaker;
+
+    #[test]
+    fn finds_win_and_block() {
+        fastrand::seed(2);
+        let evaluator = SimpleEval::new();
+        let agent = MctsAgent::new(&evaluator, MctsBudget::Iterations(3000), Rollout::Random);
+        let mut board = Connect4::new();
+        for action in [3, 0, 3, 0, 3] {
+            board.play_action(action);
+        }
+        // yellow has to block the column.
+        assert_eq!(agent.get_action(&board, board.cur_player()), 3);
+        board.play_action(1);
+        // red wins.
+        let result = agent.analyse(&board, board.cur_player()).unwrap();
+        assert_eq!(result.best_action, 3);
+        assert!(result.score > 0.9);
+    }
+
+    #[test]
+    fn tree_reuse() {
+        let evaluator = ConsequtiveEval::new();
+        let agent = MctsAgent::new(&evaluator, MctsBudget::Iterations(200), Rollout::Greedy(0.3));
+        let mut board = Stack4::new();
+        let action = agent.get_action(&board, board.cur_player());
+        board.play_action(action);
+        let reply = board.legal_actions().next().unwrap();
+        board.play_action(reply);
+        let visits = {
+            let tree = agent.tree.borrow();
+            let tree = tree.as_ref().unwrap();
+            let child = tree.nodes[0].children.iter().find(|&&c| tree.nodes[c].action == Some(action)).unwrap();
+            tree.nodes[*child].children.iter().find(|&&c| tree.nodes[c].action == Some(reply)).map_or(0, |&c| tree.nodes[c].visits)
+        };
+        agent.get_action(&board, board.cur_player());
+        let tree = agent.tree.borrow();
+        let tree = tree.as_ref().unwrap();
+        assert_eq!(tree.board.uid(), board.uid());
+        assert_eq!(tree.nodes[0].visits, visits+200);
+        for (i, node) in tree.nodes.iter().enumerate() {
+            for &c in &node.children {
+                assert_eq!(tree.nodes[c].parent, Some(i));
+            }
+        }
+    }
+
+    #[test]
+    fn against_minimax() {
+        fastrand::seed(4);
+        let evaluator = SimpleEval::new();
+        let mcts = MctsAgent::new(&evaluator, MctsBudget::Iterations(200), Rollout::Random);
+        // SimpleEval only knows won and lost positions, at depth 1 the agent takes a win when it can
+        // and otherwise abnegamax_best_action breaks the tie between the actions with fastrand, so
+        // with the seed both agents play the same games every run.
+        let minimax = MinimaxAgent::new(&evaluator, 1);
+        let mut mm = MatchMaker::<Connect4>::new();
+        mm.add_agent(&mcts);
+        mm.add_agent(&minimax);
+        mm.play_n_games(10);
+        let scores = mm.scores();
+        assert_eq!(scores[0].iter().sum::<i32>(), 10);
+        assert_eq!(scores[0][1], scores[1][2]);
+        assert!(scores[0][1] >= 9, "{:?}", scores);
+    }
+}
@@ -2,7 +2,7 @@
 use crate::evaluators::{Evaluator};
 use crate::games::{GameState, Player, Game};
 use crate::policies::Policy;
-use crate::search::{abnegamax};
+use crate::search::{abnegamax, SearchOptions};
 use crate::agents::{Agent, BatchMinimaxAgent, MinimaxPolicyAgent};
 use serde::{Serialize, Deserialize};
 
@@ -67,6 +67,10 @@ pub struct QLearning<E> {
     // Decay of eligibility trace.
     pub lambda: f64, 
 
+    // options of the searches during training, older files do not have them.
+    #[serde(default)]
+    pub search_options: SearchOptions,
+
     eligibilty_trace: Option<Vec<f64>>,
 }
 
@@ -81,6 +85,7 @@ impl<E> QLearning<E> {
             batch_depth: 0,
             scores: Vec::new(),
             lambda: 0.0, // Default is one step TD.
+            search_options: SearchOptions::default(),
             eligibilty_trace: None
         }
     }
@@ -117,7 +122,7 @@ impl<G, E> RL<G, E> for QLearning<E>
                     }
                 }
             } else {
-                let v = abnegamax(*next_state, self.depth, self.batch_depth, &self.evaluator, player, None);
+                let v = abnegamax(*next_state, self.depth, self.batch_depth, &self.evaluator, player, None, self.search_options);
 
                 // The reward is baked into the target action value.
                 if v == 1./0. {
@@ -151,15 +156,18 @@ impl<G, E> RL<G, E> for QLearning<E>
     }
 
     fn self_play(&mut self) {
-        let agenta = MinimaxPolicyAgent::new(&self.evaluator, &*self.exploration_policy, self.depth);
-        let agentb = MinimaxPolicyAgent::new(&self.evaluator, &*self.exploration_policy, self.depth);
+        let mut agenta = MinimaxPolicyAgent::new(&self.evaluator, &*self.exploration_policy, self.depth);
+        agenta.options = self.search_options;
+        let mut agentb = MinimaxPolicyAgent::new(&self.evaluator, &*self.exploration_policy, self.depth);
+        agentb.options = self.search_options;
         let game_hist: Vec<(G, bool)> = episode(&agenta, &agentb);
         self.update(&game_hist, Player::Red);
         self.update(&game_hist, Player::Yellow);
     }
 
     fn play_against(&mut self, opponent: &dyn Agent<G>) {
-        let agent = BatchMinimaxAgent::new(&self.evaluator, self.depth, self.depth);
+        let mut agent = BatchMinimaxAgent::new(&self.evaluator, self.depth, self.depth);
+        agent.options = self.search_options;
         let (game_hist, selfp) = if fastrand::bool() {
             (episode(&agent, opponent), Player::Red)
         } else {
@@ -0,0 +1,225 @@
+use crate::games::{Game, GameState, Player};
+use crate::games::notation::{ActionNotation, NotationError};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A played game together with who played it, written in a PGN-like text format:
+//
+// [Red "minimax Depth(4)"]
+// [Yellow "mcts Iterations(1000)"]
+// [Result "1-0"]
+// [Start "1760000000"]
+// [End "1760000012"]
+// 4 {0.25} 4 {-0.5} 5 3 {inf}
+//
+// The last line holds the actions in the notation of the game, each optionally followed by
+// the score of the search that chose it. Several records are separated by empty lines.
+#[derive(Clone, Debug)]
+pub struct GameRecord<G: Game> {
+    // descriptions of the agents.
+    pub red: String,
+    pub yellow: String,
+    pub result: GameState,
+    // seconds since the unix epoch.
+    pub start: u64,
+    pub end: u64,
+    pub actions: Vec<G::Action>,
+    // the score of each action as seen by the player who played it, None if it was not searched.
+    pub evaluations: Vec<Option<f64>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn format_result(result: GameState) -> &'static str {
+    match result {
+        GameState::Won(Player::Red) => "1-0",
+        GameState::Won(Player::Yellow) => "0-1",
+        GameState::Draw => "1/2-1/2",
+        GameState::InProgress => "*",
+    }
+}
+
+fn parse_result(s: &str) -> Option<GameState> {
+    match s {
+        "1-0" => Some(GameState::Won(Player::Red)),
+        "0-1" => Some(GameState::Won(Player::Yellow)),
+        "1/2-1/2" => Some(GameState::Draw),
+        "*" => Some(GameState::InProgress),
+        _ => None,
+    }
+}
+
+impl<G: Game> GameRecord<G> {
+    // An empty record of a game starting now.
+    pub fn new(red: String, yellow: String) -> Self {
+        let start = now();
+        GameRecord {
+            red,
+            yellow,
+            result: GameState::InProgress,
+            start,
+            end: start,
+            actions: Vec::new(),
+            evaluations: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, action: G::Action, evaluation: Option<f64>) {
+        self.actions.push(action);
+        self.evaluations.push(evaluation);
+    }
+
+    pub fn finish(&mut self, result: GameState) {
+        self.result = result;
+        self.end = now();
+    }
+
+    // The position after all actions, fails if any of them is illegal.
+    pub fn board(&self) -> Result<G, NotationError> {
+        let mut board = G::new();
+        for (i, &action) in self.actions.iter().enumerate() {
+            board.try_play_action(action).map_err(|err| NotationError::IllegalAction(i, err))?;
+        }
+        Ok(board)
+    }
+}
+
+impl<G: ActionNotation> fmt::Display for GameRecord<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[Red \"{}\"]", self.red)?;
+        writeln!(f, "[Yellow \"{}\"]", self.yellow)?;
+        writeln!(f, "[Result \"{}\"]", format_result(self.result))?;
+        writeln!(f, "[Start \"{}\"]", self.start)?;
+        writeln!(f, "[End \"{}\"]", self.end)?;
+        let mut moves = Vec::new();
+        for (&action, evaluation) in self.actions.iter().zip(&self.evaluations) {
+            moves.push(G::format_action(action));
+            if let Some(score) = evaluation {
+                moves.push(format!("{{{}}}", score));
+            }
+        }
+        writeln!(f, "{}", moves.join(" "))
+    }
+}
+
+// Reads a single record, the actions are checked to be legal.
+impl<G: ActionNotation> FromStr for GameRecord<G> {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<Self, NotationError> {
+        let invalid = |msg: String| NotationError::InvalidRecord(msg);
+        let mut record = GameRecord::new(String::new(), String::new());
+        record.start = 0;
+        record.end = 0;
+        let mut movetext = None;
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if movetext.is_some() {
+                return Err(invalid(format!("unexpected line after the actions '{}'", line)));
+            }
+            if !line.starts_with('[') {
+                movetext = Some(line);
+                continue;
+            }
+            let (key, value) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']'))
+                .and_then(|tag| tag.split_once(' '))
+                .and_then(|(key, value)| Some((key, value.trim().strip_prefix('"')?.strip_suffix('"')?)))
+                .ok_or_else(|| invalid(format!("invalid tag '{}'", line)))?;
+            let number = |value: &str| value.parse().map_err(|_| invalid(format!("invalid {} '{}'", key, value)));
+            match key {
+                "Red" => record.red = value.to_string(),
+                "Yellow" => record.yellow = value.to_string(),
+                "Result" => record.result = parse_result(value).ok_or_else(|| invalid(format!("invalid result '{}'", value)))?,
+                "Start" => record.start = number(value)?,
+                "End" => record.end = number(value)?,
+                _ => {}, // unknown tags are ignored.
+            }
+        }
+        for token in movetext.unwrap_or("").split_whitespace() {
+            if let Some(score) = token.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+                match record.evaluations.last_mut() {
+                    Some(evaluation @ None) => {
+                        *evaluation = Some(score.parse().map_err(|_| invalid(format!("invalid score '{}'", score)))?);
+                    },
+                    _ => return Err(invalid(format!("score '{}' without an action", token))),
+                }
+            } else {
+                record.push(G::parse_action(token)?, None);
+            }
+        }
+        record.board()?;
+        Ok(record)
+    }
+}
+
+pub fn write_records<G: ActionNotation>(records: &[GameRecord<G>]) -> String {
+    records.iter().map(|record| record.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+// Reads records separated by empty lines.
+pub fn read_records<G: ActionNotation>(s: &str) -> Result<Vec<GameRecord<G>>, NotationError> {
+    let mut records = Vec::new();
+    let mut lines = Vec::new();
+    for line in s.lines().chain(std::iter::once("")) {
+        if !line.trim().is_empty() {
+            lines.push(line);
+        } else if !lines.is_empty() {
+            records.push(lines.join("\n").parse()?);
+            lines.clear();
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::agents::MinimaxAgent;
+    use crate::evaluators::SimpleEval;
+    use crate::games::connect4::Connect4;
+    use crate::games::othello::Othello;
+    use crate::matchmaker::MatchMaker;
+    use crate::mcts::{MctsAgent, MctsBudget, Rollout};
+
+    #[test]
+    fn write_and_read() {
+        let mut record = GameRecord::<Connect4>::new("red".to_string(), "yellow \"two\"".to_string());
+        for (action, evaluation) in [(3, Some(0.25)), (3, None), (4, Some(-1.0/0.0)), (2, Some(0.1))] {
+            record.push(action, evaluation);
+        }
+        record.finish(GameState::InProgress);
+        let s = record.to_string();
+        assert!(s.ends_with("4 {0.25} 4 5 {-inf} 3 {0.1}\n"), "{}", s);
+        let read: GameRecord<Connect4> = s.parse().unwrap();
+        assert_eq!((read.red.as_str(), read.yellow.as_str()), ("red", "yellow \"two\""));
+        assert_eq!(read.actions, record.actions);
+        assert_eq!(read.evaluations, record.evaluations);
+        assert_eq!((read.start, read.end, read.result), (record.start, record.end, record.result));
+
+        assert!("[Result \"2-0\"]\n4".parse::<GameRecord<Connect4>>().is_err());
+        assert!("4 {0.1} {0.2}".parse::<GameRecord<Connect4>>().is_err());
+        assert_eq!("4 4 4 4 4 4 4".parse::<GameRecord<Connect4>>().err(), Some(NotationError::IllegalAction(6, crate::games::PlayError::ColumnFull)));
+    }
+
+    #[test]
+    fn matchmaker_records() {
+        let evaluator = SimpleEval::new();
+        let minimax = MinimaxAgent::new(&evaluator, 2);
+        let mcts = MctsAgent::new(&evaluator, MctsBudget::Iterations(50), Rollout::Random);
+        let mut mm = MatchMaker::<Othello>::new();
+        mm.evaluations = true;
+        mm.add_agent(&minimax);
+        mm.add_agent(&mcts);
+        mm.play_n_games(2);
+        let records = read_records::<Othello>(&write_records(mm.records())).unwrap();
+        assert_eq!(records.len(), 2);
+        for (record, played) in records.iter().zip(mm.records()) {
+            assert_eq!(record.actions, played.actions);
+            assert_eq!(record.board().unwrap().game_state(), record.result);
+            assert!(record.evaluations.iter().all(|e| e.is_some()));
+            assert!([&record.red, &record.yellow].contains(&&"minimax Depth(2)".to_string()));
+        }
+    }
+}
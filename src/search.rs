@@ -1,47 +1,438 @@
 
-use crate::games::{Player, GameState, Game};
+use crate::games::{ActionBuffer, Player, GameState, Game};
 use crate::evaluators::{Evaluator};
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::AddAssign;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
 
-pub static mut LEAF_COUNT: u32 = 0;
+// Upper limit on the depth of iterative deepening when searching with a time or node budget.
+const MAX_DEPTH: u32 = 64;
 
-// these two numbers must be coprime.
-const TABLE_SIZE: usize = 104723;
-const MULTIPLIER: usize = 48619;
+// How often the clock is read, in nodes. Has to be a power of two.
+const CLOCK_INTERVAL: u64 = 256;
 
-pub struct TranspositionTable<T> {
-    pub table: Vec<Option<(u128, T)>>,
+// How much effort a search may spend.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimeControl {
+    Depth(u32),
+    Time(Duration),
+    Nodes(u64),
 }
 
-pub fn hash(board: u128) -> usize {
-    (board * MULTIPLIER as u128 % TABLE_SIZE as u128) as usize
+// How the search is spread over threads.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Parallelism {
+    #[default]
+    Single,
+    // the actions at the root are divided between the threads.
+    RootSplit(usize),
+    // every thread searches the whole tree, they help each other through the shared transposition table.
+    LazySmp(usize),
 }
 
+// How a search is run, set the fields after SearchParams::new to change the defaults.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SearchParams {
+    pub time_control: TimeControl,
+    pub parallelism: Parallelism,
+    pub options: SearchOptions,
+}
+
+impl SearchParams {
+    pub fn new(time_control: TimeControl) -> SearchParams {
+        SearchParams {
+            time_control,
+            parallelism: Parallelism::Single,
+            options: SearchOptions::default(),
+        }
+    }
+}
+
+// Choices that change how the positions are searched, the same for the whole search.
+// Everything is turned off by default.
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    // key the transposition table and the leaves of batch_negamax on Game::canonical_uid so that
+    // positions that are equal under symmetry are only searched and evaluated once. The best action
+    // stored for a symmetric position is only tried if it is legal and may be worse than usual.
+    pub canonical_keys: bool,
+    // try the actions that caused cutoffs in other positions at the same ply early.
+    pub killer_moves: bool,
+    // order the remaining actions by how often they caused cutoffs anywhere in the search.
+    pub history: bool,
+    // principal variation search, every action but the first is searched with a null window that
+    // only tells whether it is better than the best so far and searched again if it is.
+    pub pvs: bool,
+    // half the width of the window around the score of the previous iteration that the actions at
+    // the root are searched with, the window is doubled until the best value is inside of it.
+    // The values of the other actions may then only be upper bounds.
+    pub aspiration_window: Option<f64>,
+    // how many plies a line may be searched past the depth while a player threatens to win next turn,
+    // so that the evaluator is not asked about positions in the middle of a forced sequence. 0 turns it off.
+    // The positions searched with batch_negamax are not extended.
+    pub threat_extension: u32,
+}
+
+impl SearchOptions {
+    // The key of 'board' in the transposition table.
+    fn tt_key<G: Game>(&self, board: &G) -> u64 {
+        if self.canonical_keys {
+            // the table picks the bucket from the key so all bits of the uid have to affect it.
+            let uid = board.canonical_uid();
+            mix(uid as u64 ^ mix((uid >> 64) as u64))
+        } else {
+            board.hash()
+        }
+    }
+}
+
+// The finalizer of splitmix64.
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// What a search found at the root position.
+#[derive(Clone, Debug)]
+pub struct SearchResult<A> {
+    pub best_action: A,
+    pub score: f64,
+    pub pv: Vec<A>, // the expected continuation, starting with best_action.
+    pub depth: u32, // depth of the last completed iteration.
+    pub root_scores: Vec<(A, f64)>, // with an aspiration window only the value of best_action is exact.
+    pub stats: SearchStats,
+}
+
+// Counters collected during a search.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SearchStats {
+    pub nodes: u64,
+    pub leaves: u64, // positions given to the evaluator, including those evaluated in batches.
+    pub tt_hits: u64,
+    pub tt_misses: u64,
+    pub cutoffs: u64,
+    pub first_cutoffs: u64, // cutoffs by the first action searched, a measure of the move ordering.
+    pub researches: u64, // searches repeated with a wider window, see SearchOptions.
+    pub extensions: u64, // positions searched past the depth because of a threat.
+    pub batch_evaluations: u64, // calls to Evaluator::values.
+    pub elapsed: Duration,
+}
+
+impl AddAssign for SearchStats {
+    fn add_assign(&mut self, other: SearchStats) {
+        self.nodes += other.nodes;
+        self.leaves += other.leaves;
+        self.tt_hits += other.tt_hits;
+        self.tt_misses += other.tt_misses;
+        self.cutoffs += other.cutoffs;
+        self.first_cutoffs += other.first_cutoffs;
+        self.researches += other.researches;
+        self.extensions += other.extensions;
+        self.batch_evaluations += other.batch_evaluations;
+        self.elapsed += other.elapsed;
+    }
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64();
+        write!(f, "nodes: {}, leaves: {}, tt hits: {}, tt misses: {}, cutoffs: {} ({} by the first action), researches: {}, extensions: {}, batch evaluations: {}, time: {:.3}s ({:.0} nodes/s)",
+            self.nodes, self.leaves, self.tt_hits, self.tt_misses, self.cutoffs, self.first_cutoffs, self.researches, self.extensions, self.batch_evaluations, 
+            secs, self.nodes as f64/secs)
+    }
+}
+
+// State shared by all nodes of a search, used to stop the search when the budget is spent.
+// Every thread has its own context, the contexts of a search share the stop flag.
+pub struct SearchContext {
+    deadline: Option<Instant>,
+    max_nodes: Option<u64>,
+    pub stats: SearchStats,
+    pub options: SearchOptions,
+    ordering: MoveOrdering,
+    // threat extensions on the path to the current node.
+    extended: u32,
+    stop: Arc<AtomicBool>,
+    stopped: bool,
+}
+
+// What the nodes of a search are given besides the position and the window, 'player' is the player the
+// value of the node is for and is switched for every ply.
+struct SearchArgs<'a, T, E> {
+    batch_depth: u32,
+    evaluator: &'a E,
+    player: Player,
+    tt: &'a TranspositionTable<T>,
+}
+
+impl<T, E> Clone for SearchArgs<'_, T, E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, E> Copy for SearchArgs<'_, T, E> {}
+
+impl<T, E> SearchArgs<'_, T, E> {
+    // The arguments for the children of the node.
+    fn opponent(self) -> Self {
+        SearchArgs { player: !self.player, ..self }
+    }
+}
+
+// The actions that caused cutoffs so far, kept by every thread for the whole search.
+#[derive(Default)]
+struct MoveOrdering {
+    // the last two actions that caused a cutoff at every ply, counted from the start of the game.
+    killers: Vec<[Option<usize>; 2]>,
+    // indexed by the player and then the action, grows by depth*depth for every cutoff.
+    history: Vec<u64>,
+}
+
+impl MoveOrdering {
+    fn killers(&self, ply: usize) -> [Option<usize>; 2] {
+        self.killers.get(ply).copied().unwrap_or_default()
+    }
+
+    fn history<T: Game>(&self, player: Player, action: T::Action) -> u64 {
+        let i = (player as usize-1)*T::action_space() + T::action_index(action);
+        self.history.get(i).copied().unwrap_or(0)
+    }
+
+    // 'action' caused a cutoff in a search of 'depth' at 'ply', only the tables enabled in 'options' are updated.
+    fn cutoff<T: Game>(&mut self, options: SearchOptions, player: Player, ply: usize, action: T::Action, depth: u32) {
+        let index = T::action_index(action);
+        if options.killer_moves {
+            if self.killers.len() <= ply {
+                self.killers.resize(ply+1, [None; 2]);
+            }
+            let killers = &mut self.killers[ply];
+            if killers[0] != Some(index) {
+                killers[1] = killers[0];
+                killers[0] = Some(index);
+            }
+        }
+        if options.history {
+            if self.history.is_empty() {
+                self.history = vec![0; 2*T::action_space()];
+            }
+            self.history[(player as usize-1)*T::action_space() + index] += depth as u64*depth as u64;
+        }
+    }
+}
+
+impl SearchContext {
+    pub fn new(time_control: TimeControl) -> SearchContext {
+        let mut ctx = SearchContext::unlimited();
+        match time_control {
+            TimeControl::Depth(_) => {},
+            TimeControl::Time(duration) => ctx.deadline = Some(Instant::now()+duration),
+            TimeControl::Nodes(n) => ctx.max_nodes = Some(n),
+        }
+        ctx
+    }
+
+    pub fn unlimited() -> SearchContext {
+        SearchContext {
+            deadline: None,
+            max_nodes: None,
+            stats: SearchStats::default(),
+            options: SearchOptions::default(),
+            ordering: MoveOrdering::default(),
+            extended: 0,
+            stop: Arc::new(AtomicBool::new(false)),
+            stopped: false,
+        }
+    }
+
+    // Contexts for 'n' threads that together have the budget of this one.
+    pub fn split(&self, n: usize) -> Vec<SearchContext> {
+        (0..n).map(|_| SearchContext {
+            deadline: self.deadline,
+            max_nodes: self.max_nodes.map(|max_nodes| max_nodes/n as u64),
+            stats: SearchStats::default(),
+            options: self.options,
+            ordering: MoveOrdering::default(),
+            extended: 0,
+            stop: self.stop.clone(),
+            stopped: false,
+        }).collect()
+    }
+
+    // Counts a visited node and returns true if the search has to stop.
+    fn tick(&mut self) -> bool {
+        self.stats.nodes += 1;
+        if let Some(max_nodes) = self.max_nodes {
+            if self.stats.nodes > max_nodes {
+                self.stop();
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if self.stats.nodes & (CLOCK_INTERVAL-1) == 0 && Instant::now() >= deadline {
+                self.stop();
+            }
+        }
+        self.stopped |= self.stop.load(Ordering::Relaxed);
+        self.stopped
+    }
+
+    // Stops this search in all threads.
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.stopped = true;
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+}
+
+// number of buckets, every bucket holds two entries.
+const DEFAULT_TABLE_SIZE: usize = 1<<16;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Bound {
+    Exact,
+    // the value is at least this large, the search failed high.
+    Lower,
+    // the value is at most this large, the search failed low.
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TTEntry<A> {
+    pub key: u64,
+    pub value: f64,
+    pub depth: u32,
+    pub bound: Bound,
+    pub best_action: Option<A>,
+}
+
+// Every bucket has a depth-preferred slot that keeps the deepest search of the positions
+// hashed to it and an always-replace slot that keeps the most recent one.
+// The table is written without locks so that it can be shared between search threads. 
+//
+// Positions are only told apart by their 64 bit key, two positions with the same key share their
+// entries and the value of one is returned for the other. With n positions in the table the chance
+// that any two collide is about n*n/2^65, which is accepted instead of storing the whole position.
+pub struct TranspositionTable<G> {
+    table: Vec<[Slot; 2]>,
+    game: PhantomData<G>,
+}
 
-impl<T: Copy> TranspositionTable<T> {
-    pub fn new() -> TranspositionTable<T> {
+// An entry is stored in three words and the first one is the key xor'ed with the other two,
+// so an entry mixed from two concurrent writes fails the key check and is ignored. 
+#[derive(Default)]
+struct Slot {
+    check: AtomicU64,
+    value: AtomicU64,
+    // bit 63: occupied, bits 48..63: action index + 1 or 0 if there is no action, 
+    // bits 32..34: bound, bits 0..32: depth
+    data: AtomicU64,
+}
+
+const OCCUPIED: u64 = 1<<63;
+
+impl Slot {
+    fn load(&self) -> Option<(u64, f64, u64)> {
+        let check = self.check.load(Ordering::Relaxed);
+        let value = self.value.load(Ordering::Relaxed);
+        let data = self.data.load(Ordering::Relaxed);
+        if data & OCCUPIED == 0 {
+            return None;
+        }
+        Some((check ^ value ^ data, f64::from_bits(value), data))
+    }
+
+    fn store(&self, key: u64, value: f64, data: u64) {
+        let value = value.to_bits();
+        self.check.store(key ^ value ^ data, Ordering::Relaxed);
+        self.value.store(value, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+
+    fn key(&self) -> Option<u64> {
+        self.load().map(|(key, _, _)| key)
+    }
+
+    fn depth(&self) -> u32 {
+        self.data.load(Ordering::Relaxed) as u32
+    }
+
+    fn clear(&self) {
+        self.data.store(0, Ordering::Relaxed);
+    }
+}
+
+impl<G: Game> TranspositionTable<G> {
+    pub fn new() -> TranspositionTable<G> {
+        TranspositionTable::with_size(DEFAULT_TABLE_SIZE)
+    }
+
+    pub fn with_size(nb_buckets: usize) -> TranspositionTable<G> {
         TranspositionTable {
-            table: vec![None; TABLE_SIZE],
+            table: (0..nb_buckets.max(1)).map(|_| Default::default()).collect(),
+            game: PhantomData,
         }
     }
 
-    pub fn get(&self, board: u128) -> Option<T> {
-        if let Some((b, val)) = &self.table[hash(board)] {
-            if board == *b {
-                Some(*val)
-            } else {
-                None
+    fn bucket(&self, key: u64) -> &[Slot; 2] {
+        &self.table[(key % self.table.len() as u64) as usize]
+    }
+
+    pub fn get(&self, key: u64) -> Option<TTEntry<G::Action>> {
+        let (_, value, data) = self.bucket(key).iter().filter_map(Slot::load).find(|(k, _, _)| *k == key)?;
+        let action = (data >> 48) as usize & 0x7fff;
+        Some(TTEntry {
+            key,
+            value,
+            depth: data as u32,
+            bound: match (data >> 32) & 3 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+            best_action: if action == 0 { None } else { Some(G::index_to_action(action-1)) },
+        })
+    }
+
+    pub fn set(&self, entry: TTEntry<G::Action>) {
+        let action = entry.best_action.map_or(0, |a| G::action_index(a) as u64 + 1);
+        let data = OCCUPIED | action << 48 | (entry.bound as u64) << 32 | entry.depth as u64;
+        let bucket = self.bucket(entry.key);
+        match bucket[0].key() {
+            Some(key) if key != entry.key && bucket[0].depth() > entry.depth => {
+                bucket[1].store(entry.key, entry.value, data);
+            },
+            _ => {
+                // don't keep an outdated copy of the same position in the other slot.
+                if bucket[1].key() == Some(entry.key) {
+                    bucket[1].clear();
+                }
+                bucket[0].store(entry.key, entry.value, data);
             }
-        } else {
-            None
         }
     }
 
-    pub fn set(&mut self, board: u128, value: T) {
-        self.table[hash(board)] = Some((board, value));
+    pub fn clear(&mut self) {
+        self.table.iter().flatten().for_each(Slot::clear);
     }
 }
 
+impl<G: Game> Default for TranspositionTable<G> {
+    fn default() -> Self {
+        TranspositionTable::new()
+    }
+}
 
 pub fn abnegamax_best_action<T, E>(board: &T, depth: u32, evaluator: &E, player: Player) -> T::Action 
     where 
@@ -49,13 +440,13 @@ pub fn abnegamax_best_action<T, E>(board: &T, depth: u32, evaluator: &E, player:
         E: Evaluator<T>,
         T::Action: Copy
 {
-    let mut tt = TranspositionTable::new();
+    let tt = TranspositionTable::new();
     let mut _board = board.clone();
     let mut avs = Vec::new();
     for action in _board.legal_actions() {
-        _board.play_action(action);
-        avs.push((action, -abnegamax(&_board, depth-1, 0, evaluator, !player, Some(&mut tt))));
-        _board.reverse_last_action(action);
+        let undo = _board.play_action(action);
+        avs.push((action, -abnegamax(&_board, depth-1, 0, evaluator, !player, Some(&tt), SearchOptions::default())));
+        _board.reverse_last_action(undo);
     }
     let mx = avs.iter().map(|(_,v)|*v).fold(-1.0/0.0, f64::max);
     let best_avs = avs.iter().filter(|(_,v)| *v==mx).collect::<Vec<&(T::Action,f64)>>();
@@ -63,6 +454,301 @@ pub fn abnegamax_best_action<T, E>(board: &T, depth: u32, evaluator: &E, player:
 }
 
 
+// Same as abnegamax_best_action but the actions at the root are divided between 'threads' threads.
+pub fn parallel_abnegamax_best_action<T, E>(board: &T, depth: u32, evaluator: &E, player: Player, threads: usize) -> T::Action 
+    where 
+        T: Game, 
+        E: Evaluator<T>,
+{
+    let tt = TranspositionTable::new();
+    let actions: Vec<T::Action> = board.legal_actions().collect();
+    let mut ctxs = SearchContext::unlimited().split(threads.max(1));
+    let args = SearchArgs { batch_depth: 0, evaluator, player, tt: &tt };
+    let avs = search_root(board, &actions, depth-1, args, &mut ctxs, (-1./0., 1./0.));
+    pick_best(&avs).expect("no legal actions").0
+}
+
+// Searches with increasing depth until the time control runs out and returns the best action
+// of the last completed iteration. 
+pub fn iterative_deepening_best_action<T, E>(board: &T, time_control: TimeControl, batch_depth: u32, 
+                                             evaluator: &E, player: Player, tt: Option<&TranspositionTable<T>>) -> T::Action 
+    where 
+        T: Game, 
+        E: Evaluator<T>,
+{
+    search(board, SearchParams::new(time_control), batch_depth, evaluator, player, tt).best_action
+}
+
+// Same as iterative_deepening_best_action but also returns the principal variation and the scores of all actions.
+pub fn search<T, E>(board: &T, params: SearchParams, batch_depth: u32, evaluator: &E, player: Player, 
+                    tt: Option<&TranspositionTable<T>>) -> SearchResult<T::Action> 
+    where 
+        T: Game, 
+        E: Evaluator<T>,
+{
+    let actions = board.legal_actions().collect();
+    if let Some(tt) = tt {
+        iterative_deepening(board, actions, params, batch_depth, evaluator, player, tt)
+    } else {
+        iterative_deepening(board, actions, params, batch_depth, evaluator, player, &TranspositionTable::new())
+    }
+}
+
+// Searches only the actions in 'actions' at the root. 
+pub(crate) fn iterative_deepening<T, E>(board: &T, actions: Vec<T::Action>, params: SearchParams, batch_depth: u32, 
+                                        evaluator: &E, player: Player, tt: &TranspositionTable<T>) -> SearchResult<T::Action> 
+    where 
+        T: Game, 
+        E: Evaluator<T>,
+{
+    assert!(!actions.is_empty(), "search called on a position without legal actions");
+    let SearchParams { time_control, parallelism, options } = params;
+    let start = Instant::now();
+    let mut ctx = SearchContext::new(time_control);
+    ctx.options = options;
+    let max_depth = match time_control {
+        TimeControl::Depth(depth) => depth,
+        _ => MAX_DEPTH,
+    };
+    let args = SearchArgs { batch_depth, evaluator, player, tt };
+    let mut result = match parallelism {
+        Parallelism::Single => {
+            deepen(board, actions, 1, max_depth, args, &mut [ctx])
+        },
+        Parallelism::RootSplit(threads) => {
+            deepen(board, actions, 1, max_depth, args, &mut ctx.split(threads.max(1)))
+        },
+        Parallelism::LazySmp(threads) => {
+            let mut ctxs = ctx.split(threads.max(1));
+            let (main, helpers) = ctxs.split_first_mut().unwrap();
+            thread::scope(|s| {
+                let handles: Vec<_> = helpers.iter_mut().enumerate().map(|(i, helper)| {
+                    // the helpers start at different depths and actions to spread out over the tree.
+                    let mut actions = actions.clone();
+                    let n = actions.len();
+                    actions.rotate_left((i+1) % n);
+                    let first_depth = (1 + (i as u32+1) % 2).min(max_depth);
+                    s.spawn(move || deepen(board, actions, first_depth, max_depth, args, std::slice::from_mut(helper)).stats)
+                }).collect();
+                let mut result = deepen(board, actions, 1, max_depth, args, std::slice::from_mut(main));
+                main.stop();
+                for handle in handles {
+                    result.stats += handle.join().unwrap();
+                }
+                result
+            })
+        },
+    };
+    result.stats.elapsed = start.elapsed();
+    result
+}
+
+// The best action of every iteration is searched first in the next one and the transposition table
+// provides the move ordering further down the tree. 
+fn deepen<T, E>(board: &T, mut actions: Vec<T::Action>, first_depth: u32, max_depth: u32, args: SearchArgs<T, E>, 
+                   ctxs: &mut [SearchContext]) -> SearchResult<T::Action> 
+    where 
+        T: Game, 
+        E: Evaluator<T>,
+{
+    let mut best: Option<SearchResult<T::Action>> = None;
+    for depth in first_depth..=max_depth {
+        let mut window = match (ctxs[0].options.aspiration_window, &best) {
+            (Some(delta), Some(best)) if best.score.is_finite() => Some((best.score, delta)),
+            _ => None,
+        };
+        let (avs, completed) = loop {
+            let (lo, hi) = window.map_or((-1./0., 1./0.), |(score, delta)| (score-delta, score+delta));
+            let avs = search_root(board, &actions, depth-1, args, ctxs, (lo, hi));
+            let completed = avs.len() == actions.len();
+            let mx = avs.iter().map(|(_,v)|*v).fold(-1./0., f64::max);
+            // the best value is only exact inside of the window, otherwise all actions are searched again with
+            // a window twice as wide or the full window for a win or loss. The values below the best are upper bounds.
+            if !completed || !((mx <= lo && lo > -1./0.) || (mx >= hi && hi < 1./0.)) {
+                break (avs, completed);
+            }
+            ctxs[0].stats.researches += 1;
+            window = window.map(|(score, delta)| (score, 2.*delta)).filter(|(_, delta)| delta.is_finite() && mx.is_finite());
+        };
+        if !completed && best.is_some() {
+            break;
+        }
+        // the first iteration is only incomplete if the budget was too small to search every action once.
+        let (action, mx) = pick_best(&avs).unwrap_or((actions[0], -1./0.));
+        best = Some(SearchResult {
+            best_action: action,
+            score: mx,
+            pv: principal_variation(board, action, depth as usize, args.tt, ctxs[0].options),
+            depth: if completed { depth } else { 0 },
+            root_scores: avs,
+            stats: SearchStats::default(),
+        });
+        if !completed || mx.is_infinite() {
+            break;
+        }
+        let i = actions.iter().position(|a| *a == action).unwrap();
+        actions[..=i].rotate_right(1);
+    }
+    let mut result = best.expect("no legal actions");
+    for ctx in ctxs {
+        result.stats += ctx.stats;
+    }
+    result
+}
+
+// Searches every action in 'actions' divided between one thread per context. Returns the values in the same order 
+// as 'actions' up to the first action that wasn't finished before the search was stopped. The actions are searched 
+// with 'window', a value at or below it is an upper bound and a value at or above it a lower bound.
+fn search_root<T, E>(board: &T, actions: &[T::Action], depth: u32, args: SearchArgs<T, E>, ctxs: &mut [SearchContext], 
+                     window: (f64, f64)) -> Vec<(T::Action, f64)>
+    where 
+        T: Game, 
+        E: Evaluator<T>,
+{
+    let next = AtomicUsize::new(0);
+    let search_actions = |ctx: &mut SearchContext| {
+        let mut board = *board;
+        let mut values = Vec::new();
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            if i >= actions.len() {
+                break;
+            }
+            let undo = board.play_action(actions[i]);
+            let (lo, hi) = window;
+            let v = -_abnegamax(&mut board, -hi, -lo, depth, args.opponent(), ctx);
+            board.reverse_last_action(undo);
+            if ctx.stopped() {
+                break;
+            }
+            values.push((i, v));
+        }
+        values
+    };
+    let mut values = vec![None; actions.len()];
+    if let [ctx] = ctxs {
+        for (i, v) in search_actions(ctx) {
+            values[i] = Some(v);
+        }
+    } else {
+        thread::scope(|s| {
+            let handles: Vec<_> = ctxs.iter_mut().map(|ctx| {
+                let search_actions = &search_actions;
+                s.spawn(move || search_actions(ctx))
+            }).collect();
+            for handle in handles {
+                for (i, v) in handle.join().unwrap() {
+                    values[i] = Some(v);
+                }
+            }
+        });
+    }
+    actions.iter().zip(values).map_while(|(a, v)| v.map(|v| (*a, v))).collect()
+}
+
+// The smallest float larger than 'x', f64::next_up needs a newer compiler.
+fn next_float(x: f64) -> f64 {
+    if x.is_nan() || x == 1./0. {
+        x
+    } else if x == 0.0 {
+        f64::from_bits(1)
+    } else if x > 0.0 {
+        f64::from_bits(x.to_bits() + 1)
+    } else {
+        f64::from_bits(x.to_bits() - 1)
+    }
+}
+
+// The action with the highest value, ties are broken randomly.
+fn pick_best<A: Copy>(avs: &[(A, f64)]) -> Option<(A, f64)> {
+    let mx = avs.iter().map(|(_,v)|*v).fold(-1./0., f64::max);
+    let best_avs = avs.iter().filter(|(_,v)| *v==mx).collect::<Vec<&(A,f64)>>();
+    if best_avs.is_empty() {
+        None
+    } else {
+        Some(*best_avs[fastrand::usize(0..best_avs.len())])
+    }
+}
+
+// The actions most likely to cause a cutoff are searched first: the best action from an earlier search of
+// the position, the actions that win, the ones that stop the opponent from winning next turn, the killer moves
+// and then the rest by their history score and in the order of Game::generate_actions. The checks for wins
+// are only done in the nodes that are searched.
+fn order_actions<T: Game>(board: &T, actions: &mut ActionBuffer<T::Action>, tt_action: Option<T::Action>, ctx: &SearchContext) {
+    let player = board.cur_player();
+    let killers = if ctx.options.killer_moves {
+        ctx.ordering.killers(board.length() as usize)
+    } else {
+        [None; 2]
+    };
+    let priority = |action| {
+        if Some(action) == tt_action {
+            0
+        } else if board.is_winning_action(action, player) {
+            1
+        } else if board.is_winning_action(action, !player) {
+            2
+        } else if killers[0] == Some(T::action_index(action)) {
+            3
+        } else if killers[1] == Some(T::action_index(action)) {
+            4
+        } else {
+            5
+        }
+    };
+    let history = |action| if ctx.options.history { ctx.ordering.history::<T>(player, action) } else { 0 };
+    // the index keeps the order of the generator among actions of the same priority and history.
+    let mut ordered: ActionBuffer<(u8, Reverse<u64>, usize, T::Action)> = actions.iter().enumerate()
+        .map(|(i, &a)| (priority(a), Reverse(history(a)), i, a))
+        .collect();
+    ordered.sort_unstable_by_key(|&(p, h, i, _)| (p, h, i));
+    for (slot, (_, _, _, action)) in actions.iter_mut().zip(ordered) {
+        *slot = action;
+    }
+}
+
+// Whether either player could win with their next piece, so that the player to move has to win or block.
+fn has_threat<T: Game>(board: &T) -> bool {
+    let mut actions = ActionBuffer::new();
+    board.generate_actions(&mut actions);
+    let player = board.cur_player();
+    actions.iter().any(|&a| board.is_winning_action(a, player) || board.is_winning_action(a, !player))
+}
+
+// Follows the best actions stored in the transposition table from the position after 'first'.
+fn principal_variation<T>(board: &T, first: T::Action, max_len: usize, tt: &TranspositionTable<T>, options: SearchOptions) -> Vec<T::Action> 
+    where
+        T: Game
+{
+    let mut board = *board;
+    let mut pv = vec![first];
+    board.play_action(first);
+    while pv.len() < max_len && board.game_state() == GameState::InProgress {
+        let next = if options.canonical_keys {
+            // the stored action may have been found in a mirrored position, so instead the child
+            // whose exact value is the negated exact value of the position is followed.
+            tt.get(options.tt_key(&board))
+                .filter(|entry| entry.bound == Bound::Exact)
+                .and_then(|entry| board.legal_actions().find(|&action| {
+                    let mut child = board;
+                    child.play_action(action);
+                    tt.get(options.tt_key(&child))
+                        .filter(|e| e.bound == Bound::Exact && e.value == -entry.value).is_some()
+                }))
+        } else {
+            tt.get(options.tt_key(&board)).and_then(|entry| entry.best_action)
+        };
+        match next {
+            Some(action) if board.legal_actions().any(|a| a == action) => {
+                board.play_action(action);
+                pv.push(action);
+            },
+            _ => break,
+        }
+    }
+    pv
+}
+
 pub fn batch_negamax_best_action<T, E>(board: &T, depth: u32, evaluator: &E, player: Player) -> T::Action 
     where 
         T: Game, 
@@ -72,9 +758,9 @@ pub fn batch_negamax_best_action<T, E>(board: &T, depth: u32, evaluator: &E, pla
     let mut _board = board.clone();
     let mut avs = Vec::new();
     for action in _board.legal_actions() {
-        _board.play_action(action);
-        avs.push((action, -batch_negamax(&_board, depth-1, evaluator, !player)));
-        _board.reverse_last_action(action);
+        let undo = _board.play_action(action);
+        avs.push((action, -batch_negamax(&_board, depth-1, evaluator, !player, &mut SearchContext::unlimited())));
+        _board.reverse_last_action(undo);
     }
     //println!("{:?}", avs);
     let mx = avs.iter().map(|(_,v)|*v).fold(-1.0/0.0, f64::max);
@@ -89,13 +775,13 @@ pub fn batch_abnegamax_best_action<T, E>(board: &T, depth: u32, batch_depth: u32
         E: Evaluator<T>,
         T::Action: Copy
 {
-    let mut tt = TranspositionTable::new();
+    let tt = TranspositionTable::new();
     let mut _board = board.clone();
     let mut avs = Vec::new();
     for action in _board.legal_actions() {
-        _board.play_action(action);
-        avs.push((action, -abnegamax(&_board, depth-1, batch_depth, evaluator, !player, Some(&mut tt))));
-        _board.reverse_last_action(action);
+        let undo = _board.play_action(action);
+        avs.push((action, -abnegamax(&_board, depth-1, batch_depth, evaluator, !player, Some(&tt), SearchOptions::default())));
+        _board.reverse_last_action(undo);
     }
     //println!("{:?}", avs);
     let mx = avs.iter().map(|(_,v)|*v).fold(-1.0/0.0, f64::max);
@@ -114,72 +800,144 @@ pub fn negamax<T, E>(board: &mut T, depth: u32, evaluator: &E, player: Player) -
         return evaluator.value(board, player);
     }
     let mut val: f64 = -1./0.;
-    for action in board.legal_actions() {
-        board.play_action(action);
+    let mut actions = ActionBuffer::new();
+    board.generate_actions(&mut actions);
+    for action in actions {
+        let undo = board.play_action(action);
         let v = -negamax(board, depth-1, evaluator, !player);
-        board.reverse_last_action(action);
+        board.reverse_last_action(undo);
         val = val.max(v);
     }
     val
 }
 
-pub fn abnegamax<T, E>(board: &T, depth: u32, batch_depth: u32, 
-                       evaluator: &E, player: Player, tt: Option<&mut TranspositionTable<f64>>) -> f64 
+pub fn abnegamax<T, E>(board: &T, depth: u32, batch_depth: u32, evaluator: &E, player: Player, 
+                       tt: Option<&TranspositionTable<T>>, options: SearchOptions) -> f64 
     where 
         T: Game, 
         E: Evaluator<T>,
         T::Action: Copy
 {
     let mut _board = board.clone();
-    if let Some(t) = tt {
-        _abnegamax(&mut _board, -1./0., 1./0., depth, batch_depth, evaluator, player, t)
+    let mut ctx = SearchContext::unlimited();
+    ctx.options = options;
+    if let Some(tt) = tt {
+        _abnegamax(&mut _board, -1./0., 1./0., depth, SearchArgs { batch_depth, evaluator, player, tt }, &mut ctx)
     } else {
-        let mut t = TranspositionTable::new();
-        _abnegamax(&mut _board, -1./0., 1./0., depth, batch_depth, evaluator, player, &mut t)
+        let tt = &TranspositionTable::new();
+        _abnegamax(&mut _board, -1./0., 1./0., depth, SearchArgs { batch_depth, evaluator, player, tt }, &mut ctx)
     }
 }
 
-fn _abnegamax<T,E>(board: &mut T, mut alpha: f64, mut beta: f64, depth: u32, batch_depth: u32, 
-                   evaluator: &E, player: Player, tt: &mut TranspositionTable<f64>) -> f64
+fn _abnegamax<T,E>(board: &mut T, mut alpha: f64, mut beta: f64, depth: u32, args: SearchArgs<T, E>, ctx: &mut SearchContext) -> f64
     where 
         T: Game, 
         E: Evaluator<T>,
         T::Action: Copy
 {
+    let SearchArgs { batch_depth, evaluator, player, tt } = args;
+    // a position at the horizon where a player threatens to win is searched one ply deeper instead,
+    // a win is found right away and a block has to be played.
+    if depth == 0 && ctx.extended < ctx.options.threat_extension && board.game_state() == GameState::InProgress && has_threat(board) {
+        ctx.stats.extensions += 1;
+        ctx.extended += 1;
+        let v = _abnegamax(board, alpha, beta, 1, args, ctx);
+        ctx.extended -= 1;
+        return v;
+    }
+    // the value is thrown away by the caller when the search is stopped.
+    if ctx.tick() {
+        return 0.0;
+    }
     if board.game_state() != GameState::InProgress || depth == 0 {
-        unsafe {LEAF_COUNT += 1};
+        ctx.stats.leaves += 1;
         return evaluator.value(board, player);
     }
-    let mut max = 1./0.;
-    if let Some(val) = tt.get(board.uid()) {
-        max = val;
+    if let Some(value) = evaluator.exact_value(board, player) {
+        ctx.stats.leaves += 1;
+        return value;
     }
-    if beta > max {
-        beta = max;
-        if alpha >= beta {
-            return beta;
+    let alpha_orig = alpha;
+    let mut tt_action = None;
+    let key = ctx.options.tt_key(board);
+    if let Some(entry) = tt.get(key) {
+        ctx.stats.tt_hits += 1;
+        tt_action = entry.best_action;
+        // values from shallower searches are only used for move ordering.
+        if entry.depth >= depth {
+            match entry.bound {
+                Bound::Exact => return entry.value,
+                Bound::Lower => alpha = alpha.max(entry.value),
+                Bound::Upper => beta = beta.min(entry.value),
+            }
+            if alpha >= beta {
+                return entry.value;
+            }
         }
+    } else {
+        ctx.stats.tt_misses += 1;
     }
+    let mut actions = ActionBuffer::new();
+    board.generate_actions(&mut actions);
+    order_actions(board, &mut actions, tt_action, ctx);
     let mut val: f64 = -1./0.;
-    for action in board.legal_actions() {
-        board.play_action(action);
+    let mut best_action = None;
+    for (i, action) in actions.into_iter().enumerate() {
+        let undo = board.play_action(action);
         let v = if depth <= batch_depth {
-            -batch_negamax(board, depth-1, evaluator, !player)
+            -batch_negamax(board, depth-1, evaluator, !player, ctx)
+        } else if i > 0 && ctx.options.pvs {
+            // no value fits between alpha and the next float, so the null window tells whether v > alpha.
+            let v = -_abnegamax(board, -next_float(alpha), -alpha, depth-1, args.opponent(), ctx);
+            if v > alpha && v < beta && !ctx.stopped() {
+                ctx.stats.researches += 1;
+                -_abnegamax(board, -beta, -alpha, depth-1, args.opponent(), ctx)
+            } else {
+                v
+            }
         } else {
-            -_abnegamax(board, -beta, -alpha, depth-1, batch_depth, evaluator, !player, tt)
+            -_abnegamax(board, -beta, -alpha, depth-1, args.opponent(), ctx)
         };
-        val = val.max(v);
-        board.reverse_last_action(action);
+        board.reverse_last_action(undo);
+        if ctx.stopped() {
+            return 0.0;
+        }
+        if v > val || best_action.is_none() {
+            val = v;
+            best_action = Some(action);
+        }
         alpha = alpha.max(val);
         if alpha >= beta {
+            ctx.stats.cutoffs += 1;
+            if i == 0 {
+                ctx.stats.first_cutoffs += 1;
+            }
+            // winning actions are always tried early anyway.
+            let ordered = ctx.options.killer_moves || ctx.options.history;
+            if ordered && !board.is_winning_action(action, board.cur_player()) {
+                ctx.ordering.cutoff::<T>(ctx.options, board.cur_player(), board.length() as usize, action, depth);
+            }
             break;
         }
     }
-    tt.set(board.uid(), alpha);
+    let bound = if alpha <= alpha_orig {
+        Bound::Upper
+    } else if alpha >= beta {
+        Bound::Lower
+    } else {
+        Bound::Exact
+    };
+    tt.set(TTEntry {
+        key,
+        value: alpha,
+        depth,
+        bound,
+        best_action,
+    });
     alpha
 }
 
-pub fn batch_negamax<T, E>(board: &T, depth: u32, evaluator: &E, player: Player) -> f64 
+pub fn batch_negamax<T, E>(board: &T, depth: u32, evaluator: &E, player: Player, ctx: &mut SearchContext) -> f64 
     where 
         T: Game, 
         E: Evaluator<T>,
@@ -187,42 +945,55 @@ pub fn batch_negamax<T, E>(board: &T, depth: u32, evaluator: &E, player: Player)
 {
     let mut _board = board.clone();
     let leafs = leafs(&mut _board, depth);
-    let mut vals: HashMap<u128, f64> = HashMap::new();
-    let mut leaf_vals = Vec::with_capacity(leafs.len());
+    // the index of the value of every leaf, symmetric leaves share a value so only one of them is evaluated.
+    let mut unique = Vec::new();
+    let indices: Vec<usize> = if ctx.options.canonical_keys {
+        let mut seen = HashMap::new();
+        leafs.into_iter().map(|leaf| *seen.entry(leaf.canonical_uid()).or_insert_with(|| {
+            unique.push(leaf);
+            unique.len()-1
+        })).collect()
+    } else {
+        unique = leafs;
+        (0..unique.len()).collect()
+    };
+    ctx.stats.leaves += unique.len() as u64;
+    ctx.stats.batch_evaluations += 1;
     
     // compute leaf values in batch
-    if depth % 2 == 0 {
-        leaf_vals.append(&mut evaluator.values(&leafs, player));
+    let values = if depth % 2 == 0 {
+        evaluator.values(&unique, player)
     } else {
-        leaf_vals.append(&mut evaluator.values(&leafs, !player));
-    }
-
-    for (i,leaf_val) in leaf_vals.iter().enumerate() {
-        vals.insert(leafs[i].uid(),*leaf_val);
-    }
-
-    negamax_from_hashmap(&mut _board, depth, evaluator, player, &vals)
-
+        evaluator.values(&unique, !player)
+    };
+    let mut leaf_values = indices.into_iter().map(|i| values[i]);
+    negamax_from_values(&mut _board, depth, evaluator, player, &mut leaf_values)
 }
 
-fn negamax_from_hashmap<T, E>(board: &mut T, depth: u32, evaluator: &E, player: Player, hmap: &HashMap<u128, f64>) -> f64
+// Negamax where the values at the depth are taken from 'values', in the order the leaves are returned by 'leafs'.
+fn negamax_from_values<T, E>(board: &mut T, depth: u32, evaluator: &E, player: Player, values: &mut impl Iterator<Item = f64>) -> f64
 where 
     T: Game, 
     E: Evaluator<T>,
     T::Action: Copy
 {
+    if depth == 0 {
+        let value = values.next().expect("a value for every leaf");
+        if board.game_state() == GameState::InProgress {
+            return value;
+        }
+    }
     if board.game_state() != GameState::InProgress {
         return evaluator.value(board, player);
     }
-    if depth == 0 {
-        return hmap[&board.uid()];
-    }
     let mut val: f64 = -1./0.;
-    for action in board.legal_actions() {
-        board.play_action(action);
-        let v = -negamax_from_hashmap(board, depth-1, evaluator, !player, &hmap);
+    let mut actions = ActionBuffer::new();
+    board.generate_actions(&mut actions);
+    for action in actions {
+        let undo = board.play_action(action);
+        let v = -negamax_from_values(board, depth-1, evaluator, !player, values);
         val = val.max(v);
-        board.reverse_last_action(action);
+        board.reverse_last_action(undo);
     }
     val
 }
@@ -239,10 +1010,12 @@ pub fn leafs<T>(board: &mut T, depth: u32) -> Vec<T>
         return Vec::new();
     }
     let mut ret = Vec::new();
-    for action in board.legal_actions() {
-        board.play_action(action);
+    let mut actions = ActionBuffer::new();
+    board.generate_actions(&mut actions);
+    for action in actions {
+        let undo = board.play_action(action);
         ret.append(&mut leafs(board, depth-1));
-        board.reverse_last_action(action);
+        board.reverse_last_action(undo);
     }
     ret
 }
@@ -250,16 +1023,425 @@ pub fn leafs<T>(board: &mut T, depth: u32) -> Vec<T>
 #[cfg(test)]
 mod test {
     use crate::games::connect4::Connect4;
+    use crate::games::stack4::Stack4;
     use crate::games::Game;
+    use crate::evaluators::{ConsequtiveEval, SimpleEval};
+    use crate::agents::{Agent, MinimaxAgent};
     use super::*;
+    fn entry(key: u64, depth: u32) -> TTEntry<usize> {
+        TTEntry {
+            key,
+            value: depth as f64,
+            depth,
+            bound: Bound::Exact,
+            best_action: Some(3),
+        }
+    }
+
     #[test]
     fn transposition_table() {
 
-        let mut tt: TranspositionTable<f64> = TranspositionTable::new();
+        let tt = TranspositionTable::<Connect4>::new();
         let mut board = Connect4::new();
         board.play_action(4);
         board.play_action(5);
-        tt.set(board.uid(), 1.0);
-        assert_eq!(tt.get(board.uid()), Some(1.0));
+        tt.set(TTEntry {key: board.hash(), value: 1.0, depth: 2, bound: Bound::Lower, best_action: Some(3)});
+        let e = tt.get(board.hash()).unwrap();
+        assert_eq!((e.value, e.depth, e.bound, e.best_action), (1.0, 2, Bound::Lower, Some(3)));
+        board.play_action(3);
+        assert!(tt.get(board.hash()).is_none());
+    }
+
+    fn random_position<G: Game>(nb_moves: u32) -> G {
+        loop {
+            let mut board = G::new();
+            while board.length() < nb_moves && board.game_state() == GameState::InProgress {
+                let actions: Vec<_> = board.legal_actions().collect();
+                board.play_action(actions[fastrand::usize(0..actions.len())]);
+            }
+            if board.game_state() == GameState::InProgress {
+                return board;
+            }
+        }
+    }
+
+    // Searches 'board' with increasing depth reusing the same table and checks that every
+    // iteration gives the same value as a search without alpha-beta pruning.
+    fn compare_with_negamax<G, E>(board: &G, max_depth: u32, batch_depth: u32, evaluator: &E, tt: &TranspositionTable<G>)
+        where
+            G: Game,
+            E: Evaluator<G>,
+    {
+        let player = board.cur_player();
+        for depth in 1..=max_depth {
+            let expected = negamax(&mut board.clone(), depth, evaluator, player);
+            let v = abnegamax(board, depth, batch_depth, evaluator, player, Some(tt), SearchOptions::default());
+            assert_eq!(v, expected, "depth {} {:?}", depth, board);
+        }
+    }
+
+    #[test]
+    fn tt_search_matches_negamax() {
+        fastrand::seed(4);
+        let evaluator = ConsequtiveEval { params: vec![0.1, 0.4, 1.3, -0.2, -0.5, -1.1] };
+        for i in 0..12 {
+            let board: Connect4 = random_position(4+i);
+            compare_with_negamax(&board, 5, 0, &evaluator, &TranspositionTable::new());
+            compare_with_negamax(&board, 4, 1, &evaluator, &TranspositionTable::new());
+            // a single bucket makes every position collide.
+            compare_with_negamax(&board, 4, 0, &evaluator, &TranspositionTable::with_size(1));
+            compare_with_negamax(&board, 5, 0, &SimpleEval::new(), &TranspositionTable::new());
+        }
+        for i in 0..4 {
+            let board: Stack4 = random_position(6+2*i);
+            compare_with_negamax(&board, 3, 0, &evaluator, &TranspositionTable::new());
+            compare_with_negamax(&board, 3, 0, &evaluator, &TranspositionTable::with_size(7));
+        }
+    }
+
+    #[test]
+    fn tt_reused_along_game() {
+        // with a fixed depth, positions later in the game only find shallower entries from earlier searches.
+        fastrand::seed(9);
+        let evaluator = ConsequtiveEval { params: vec![0.2, 0.3, 0.9, -0.1, -0.6, -1.0] };
+        let tt = TranspositionTable::new();
+        let mut board = Connect4::new();
+        while board.game_state() == GameState::InProgress && board.length() < 14 {
+            let player = board.cur_player();
+            let expected = negamax(&mut board.clone(), 4, &evaluator, player);
+            assert_eq!(abnegamax(&board, 4, 0, &evaluator, player, Some(&tt), SearchOptions::default()), expected);
+            let actions: Vec<_> = board.legal_actions().collect();
+            board.play_action(actions[fastrand::usize(0..actions.len())]);
+        }
+    }
+
+    #[test]
+    fn iterative_deepening_depth() {
+        fastrand::seed(3);
+        let evaluator = ConsequtiveEval { params: vec![0.1, 0.4, 1.3, -0.2, -0.5, -1.1] };
+        for i in 0..6 {
+            let board: Connect4 = random_position(2*i);
+            let player = board.cur_player();
+            let actions: Vec<_> = board.legal_actions().collect();
+            let result = iterative_deepening(&board, actions, SearchParams::new(TimeControl::Depth(4)), 0, &evaluator, player, &TranspositionTable::new());
+            assert_eq!(result.depth, 4);
+            assert_eq!(result.score, negamax(&mut board.clone(), 4, &evaluator, player));
+            for &(action, v) in &result.root_scores {
+                let mut child = board;
+                child.play_action(action);
+                assert_eq!(v, -negamax(&mut child, 3, &evaluator, !player));
+            }
+            assert_eq!(result.pv[0], result.best_action);
+            assert!(result.pv.len() <= 4);
+            let mut line = board;
+            for &action in &result.pv {
+                assert!(line.legal_actions().any(|a| a == action));
+                line.play_action(action);
+            }
+        }
+    }
+
+    #[test]
+    fn budgets() {
+        let evaluator = ConsequtiveEval { params: vec![0.1, 0.4, 1.3, -0.2, -0.5, -1.1] };
+        let board = Stack4::new();
+        let start = Instant::now();
+        let action = iterative_deepening_best_action(&board, TimeControl::Time(Duration::from_millis(100)), 0, &evaluator, board.cur_player(), None);
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(board.legal_actions().any(|a| a == action));
+
+        let mut ctx = SearchContext::new(TimeControl::Nodes(500));
+        let tt = TranspositionTable::new();
+        let args = SearchArgs { batch_depth: 0, evaluator: &evaluator, player: board.cur_player(), tt: &tt };
+        let _ = _abnegamax(&mut board.clone(), -1./0., 1./0., 5, args, &mut ctx);
+        assert!(ctx.stopped());
+        assert_eq!(ctx.stats.nodes, 501);
+
+        // a budget too small for a single iteration still gives a legal action.
+        let action = iterative_deepening_best_action(&board, TimeControl::Nodes(3), 0, &evaluator, board.cur_player(), None);
+        assert!(board.legal_actions().any(|a| a == action));
+
+        // a forced win stops the iterations early.
+        let mut board = Connect4::new();
+        for mv in [3, 0, 3, 0, 3, 0] {
+            board.play_action(mv);
+        }
+        let action = iterative_deepening_best_action(&board, TimeControl::Time(Duration::from_secs(60)), 0, &evaluator, board.cur_player(), None);
+        assert_eq!(action, 3);
+    }
+
+    #[test]
+    fn parallel_search() {
+        fastrand::seed(5);
+        let evaluator = ConsequtiveEval { params: vec![0.1, 0.4, 1.3, -0.2, -0.5, -1.1] };
+        for i in 0..4 {
+            let connect4: Connect4 = random_position(4+2*i);
+            let stack4: Stack4 = random_position(4+2*i);
+            compare_parallel(&connect4, 5, &evaluator);
+            compare_parallel(&stack4, 3, &evaluator);
+        }
+        let board = Connect4::new();
+        let action = parallel_abnegamax_best_action(&board, 4, &evaluator, board.cur_player(), 3);
+        assert!(board.legal_actions().any(|a| a == action));
+    }
+
+    // All parallel modes give the same values as a single threaded search of the same depth.
+    fn compare_parallel<G, E>(board: &G, depth: u32, evaluator: &E) 
+        where
+            G: Game,
+            E: Evaluator<G>,
+    {
+        let player = board.cur_player();
+        let single = search(board, SearchParams::new(TimeControl::Depth(depth)), 0, evaluator, player, None);
+        for parallelism in [Parallelism::RootSplit(3), Parallelism::LazySmp(3)] {
+            let result = search(board, SearchParams { parallelism, ..SearchParams::new(TimeControl::Depth(depth)) }, 0, evaluator, player, None);
+            assert_eq!(result.score, single.score);
+            // a win or loss can be found in an earlier iteration with help from the other threads.
+            if result.score.is_infinite() {
+                continue;
+            }
+            assert_eq!(result.depth, depth);
+            let mut scores = result.root_scores.clone();
+            for (action, v) in &single.root_scores {
+                let i = scores.iter().position(|(a,_)| a == action).unwrap();
+                assert_eq!(scores.remove(i).1, *v, "{:?} {:?}", parallelism, board);
+            }
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let evaluator = ConsequtiveEval { params: vec![0.1, 0.4, 1.3, -0.2, -0.5, -1.1] };
+        let board = Stack4::new();
+        let result = search(&board, SearchParams::new(TimeControl::Depth(4)), 0, &evaluator, board.cur_player(), None);
+        let stats = result.stats;
+        assert_eq!(stats.tt_hits + stats.tt_misses, stats.nodes - stats.leaves);
+        assert!(stats.cutoffs > 0 && stats.tt_hits > 0);
+        assert_eq!(stats.batch_evaluations, 0);
+
+        let result = search(&board, SearchParams::new(TimeControl::Depth(4)), 1, &evaluator, board.cur_player(), None);
+        assert!(result.stats.batch_evaluations > 0);
+        assert!(result.stats.leaves > result.stats.nodes);
+    }
+
+    #[test]
+    fn canonical_keys() {
+        // the evaluators give symmetric positions the same value, so the keys do not change the values.
+        fastrand::seed(6);
+        let options = SearchOptions { canonical_keys: true, ..SearchOptions::default() };
+        let evaluator = ConsequtiveEval { params: vec![0.1, 0.4, 1.3, -0.2, -0.5, -1.1] };
+        for i in 0..6 {
+            let board: Connect4 = random_position(2+2*i);
+            let player = board.cur_player();
+            let expected = negamax(&mut board.clone(), 5, &evaluator, player);
+            for batch_depth in [0, 2] {
+                assert_eq!(abnegamax(&board, 5, batch_depth, &evaluator, player, None, options), expected, "{:?}", board);
+            }
+        }
+        for i in 0..3 {
+            let board: Stack4 = random_position(4+2*i);
+            let player = board.cur_player();
+            let expected = negamax(&mut board.clone(), 3, &SimpleEval::new(), player);
+            assert_eq!(abnegamax(&board, 3, 0, &SimpleEval::new(), player, None, options), expected);
+        }
+        // the mirrored children of the symmetric starting position are only searched once.
+        let board = Connect4::new();
+        let plain = search(&board, SearchParams::new(TimeControl::Depth(5)), 0, &evaluator, Player::Red, None);
+        let canonical = search(&board, SearchParams { options, ..SearchParams::new(TimeControl::Depth(5)) }, 0, &evaluator, Player::Red, None);
+        assert_eq!(canonical.score, plain.score);
+        assert!(canonical.stats.nodes < plain.stats.nodes);
+        // the pv leads to a position with the searched score, even where the table holds mirrored positions.
+        for i in 0..6 {
+            let board: Connect4 = random_position(3+2*i);
+            let player = board.cur_player();
+            let result = search(&board, SearchParams { options, ..SearchParams::new(TimeControl::Depth(5)) }, 0, &evaluator, player, None);
+            let mut leaf = board;
+            for &action in &result.pv {
+                leaf.play_action(action);
+            }
+            if result.pv.len() == 5 && leaf.game_state() == GameState::InProgress {
+                assert_eq!(evaluator.value(&leaf, player), result.score, "{:?}", board);
+            }
+        }
+    }
+
+    #[test]
+    fn move_ordering() {
+        let mut board = Connect4::new();
+        for mv in [0, 6, 0, 6, 0, 5] {
+            board.play_action(mv);
+        }
+        let mut actions = ActionBuffer::new();
+        board.generate_actions(&mut actions);
+        assert_eq!(actions.as_slice(), [3, 4, 2, 5, 1, 6, 0]);
+        order_actions(&board, &mut actions, None, &SearchContext::unlimited());
+        assert_eq!(actions.as_slice(), [0, 3, 4, 2, 5, 1, 6]);
+        board.play_action(1);
+        let mut ordered = ActionBuffer::new();
+        board.generate_actions(&mut ordered);
+        // the best action of an earlier search goes before the block.
+        order_actions(&board, &mut ordered, Some(4), &SearchContext::unlimited());
+        assert_eq!(ordered.as_slice(), [4, 0, 3, 2, 5, 1, 6]);
+    }
+
+    #[test]
+    fn killers_and_history() {
+        // the heuristics only change the order of the actions and save nodes on average.
+        fastrand::seed(8);
+        let evaluator = ConsequtiveEval { params: vec![0.1, 0.4, 1.3, -0.2, -0.5, -1.1] };
+        let heuristics = SearchOptions { killer_moves: true, history: true, ..SearchOptions::default() };
+        let mut nodes = [0, 0];
+        for i in 0..4 {
+            let board: Stack4 = random_position(4+2*i);
+            let player = board.cur_player();
+            let results: Vec<_> = [SearchOptions::default(), heuristics].into_iter().map(|options| {
+                search(&board, SearchParams { options, ..SearchParams::new(TimeControl::Depth(4)) }, 0, &evaluator, player, None)
+            }).collect();
+            assert_eq!(results[0].score, results[1].score);
+            for (action, v) in &results[0].root_scores {
+                assert!(results[1].root_scores.contains(&(*action, *v)));
+            }
+            for (n, result) in nodes.iter_mut().zip(&results) {
+                *n += result.stats.nodes;
+            }
+        }
+        assert!(nodes[1] < nodes[0], "{:?}", nodes);
+    }
+
+    #[test]
+    fn pvs_and_aspiration() {
+        // the windows only decide how much of the tree is searched, every value at the root stays the same.
+        fastrand::seed(10);
+        let evaluator = ConsequtiveEval { params: vec![0.1, 0.4, 1.3, -0.2, -0.5, -1.1] };
+        let variants = [
+            SearchOptions { pvs: true, ..SearchOptions::default() },
+            SearchOptions { aspiration_window: Some(0.05), ..SearchOptions::default() },
+            SearchOptions { pvs: true, aspiration_window: Some(0.5), ..SearchOptions::default() },
+        ];
+        let mut researches = 0;
+        for i in 0..6 {
+            let connect4: Connect4 = random_position(2+2*i);
+            let stack4: Stack4 = random_position(4+2*i);
+            for options in variants {
+                researches += compare_options(&connect4, 6, &evaluator, options);
+                researches += compare_options(&stack4, 3, &evaluator, options);
+            }
+            let player = connect4.cur_player();
+            let expected = negamax(&mut connect4.clone(), 5, &evaluator, player);
+            assert_eq!(abnegamax(&connect4, 5, 0, &evaluator, player, None, variants[0]), expected);
+        }
+        assert!(researches > 0);
+
+        assert_eq!(next_float(1.0), 1.0 + f64::EPSILON);
+        assert_eq!(next_float(-1./0.), f64::MIN);
+        assert_eq!(next_float(f64::MAX), 1./0.);
+        for x in [0.0, -0.0] {
+            assert_eq!(next_float(x), f64::from_bits(1));
+        }
+        assert!(next_float(-f64::from_bits(1)) == 0.0);
+    }
+
+    #[test]
+    fn threat_extension() {
+        // yellow has three in the first column and red has to block it.
+        let mut board = Connect4::new();
+        for action in [3, 0, 3, 0, 6, 0] {
+            board.play_action(action);
+        }
+        let evaluator = SimpleEval::new();
+        let player = board.cur_player();
+        let plain = search(&board, SearchParams::new(TimeControl::Depth(1)), 0, &evaluator, player, None);
+        assert!(plain.root_scores.iter().all(|(_, v)| v.is_finite()));
+        assert_eq!(plain.stats.extensions, 0);
+        // at the horizon yellow still has the threat after every action but the block, the extension finds the win.
+        let options = SearchOptions { threat_extension: 2, ..SearchOptions::default() };
+        let result = search(&board, SearchParams { options, ..SearchParams::new(TimeControl::Depth(1)) }, 0, &evaluator, player, None);
+        for (action, v) in &result.root_scores {
+            assert_eq!(v.is_finite(), *action == 0, "{} {}", action, v);
+        }
+        assert_eq!(result.best_action, 0);
+        assert!(result.stats.extensions > 0);
+
+        let mut agent = MinimaxAgent::new(&evaluator, 1);
+        agent.options = options;
+        assert_eq!(agent.analyse(&board, player).unwrap().score, result.score);
+
+        // with no threat at the horizon nothing is extended and the values are the same as without the extension.
+        fastrand::seed(3);
+        let mut unextended = 0;
+        for i in 0..10 {
+            let board: Connect4 = random_position(i);
+            let player = board.cur_player();
+            let result = search(&board, SearchParams { options, ..SearchParams::new(TimeControl::Depth(2)) }, 0, &evaluator, player, None);
+            if result.stats.extensions == 0 {
+                unextended += 1;
+                assert_eq!(result.score, negamax(&mut board.clone(), 2, &evaluator, player), "{:?}", board);
+            }
+        }
+        assert!(unextended > 0);
+    }
+
+    // Searches with 'options' and with the default options and checks that the values at the root are the same,
+    // except for values below the score that the aspiration window only bounds from above. Returns the number of researches.
+    fn compare_options<G, E>(board: &G, depth: u32, evaluator: &E, options: SearchOptions) -> u64
+        where
+            G: Game,
+            E: Evaluator<G>,
+    {
+        let player = board.cur_player();
+        let expected = search(board, SearchParams::new(TimeControl::Depth(depth)), 0, evaluator, player, None);
+        let result = search(board, SearchParams { options, ..SearchParams::new(TimeControl::Depth(depth)) }, 0, evaluator, player, None);
+        assert_eq!(result.score, expected.score, "{:?} {:?}", options, board);
+        assert_eq!(result.depth, expected.depth);
+        assert!(result.root_scores.contains(&(result.best_action, result.score)));
+        for (action, v) in &expected.root_scores {
+            let (_, bound) = result.root_scores.iter().find(|(a, _)| a == action).unwrap();
+            let consistent = bound == v || (options.aspiration_window.is_some() && v <= bound && *bound < result.score);
+            assert!(consistent, "{:?} {:?} {:?} {} {}", options, board, action, v, bound);
+        }
+        result.stats.researches
+    }
+
+    #[test]
+    fn key_collisions() {
+        // every position within 8 plies of the start has its own hash.
+        let mut hashes = HashMap::new();
+        let mut stack = vec![Connect4::new()];
+        while let Some(board) = stack.pop() {
+            if let Some(uid) = hashes.insert(board.hash(), board.uid()) {
+                assert_eq!(uid, board.uid(), "{:?}", board);
+                continue;
+            }
+            if board.length() < 8 && board.game_state() == GameState::InProgress {
+                for action in board.legal_actions() {
+                    let mut next = board;
+                    next.play_action(action);
+                    stack.push(next);
+                }
+            }
+        }
+        assert!(hashes.len() > 10000);
+    }
+
+    #[test]
+    fn replacement() {
+        // every key goes to the same bucket.
+        let tt = TranspositionTable::<Connect4>::with_size(1);
+        tt.set(entry(1, 5));
+        tt.set(entry(2, 3));
+        // the deep entry is kept, the shallow one goes in the always-replace slot.
+        assert_eq!(tt.get(1).unwrap().depth, 5);
+        assert_eq!(tt.get(2).unwrap().depth, 3);
+        tt.set(entry(3, 1));
+        assert!(tt.get(2).is_none());
+        assert_eq!(tt.get(3).unwrap().depth, 1);
+        // a deeper search takes the depth-preferred slot.
+        tt.set(entry(4, 6));
+        assert_eq!(tt.get(4).unwrap().depth, 6);
+        assert!(tt.get(1).is_none());
+        // the same position is always updated, even by a shallower search.
+        tt.set(entry(4, 2));
+        assert_eq!(tt.get(4).unwrap().depth, 2);
+        assert_eq!(tt.get(3).unwrap().depth, 1);
     }
 }
\ No newline at end of file
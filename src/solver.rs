@@ -0,0 +1,493 @@
+use crate::games::connect4::{Connect4Board, Action, BOARD_WIDTH, BOARD_HEIGHT};
+use crate::games::{Game, GameState};
+
+// Scores follow the convention where a positive score means that the player to move wins and
+// the score is the number of pieces that player has left when it places the winning piece.
+// A negative score means that the opponent wins in the same way and 0 is a draw.
+// These are the bounds for the standard board, see Solver::MIN_SCORE for other sizes.
+pub const MIN_SCORE: i32 = -((BOARD_WIDTH*BOARD_HEIGHT) as i32)/2 + 3;
+pub const MAX_SCORE: i32 = ((BOARD_WIDTH*BOARD_HEIGHT) as i32 + 1)/2 - 3;
+
+// prime number of entries, uses roughly 72MB.
+const DEFAULT_TABLE_SIZE: usize = (1<<23)+9;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+    // the player to move wins, the number is how many plies are left when both sides play perfectly.
+    Win(u32),
+    // the player to move loses, the number is how many plies are left when both sides play perfectly.
+    Loss(u32),
+    Draw,
+}
+
+impl Outcome {
+    // Converts a score for 'board' to an outcome.
+    pub fn from_score<const W: usize, const H: usize, const K: usize>(score: i32, board: &Connect4Board<W, H, K>) -> Outcome {
+        let nb_cells = (W*H) as u32;
+        let nb_moves = board.nb_moves;
+        if score == 0 {
+            return Outcome::Draw;
+        }
+        // the winning piece is placed when 'winning_move' moves have been played and
+        // the winner has to move then, which decides the parity of 'winning_move'.
+        let winner_parity = if score > 0 {nb_moves%2} else {(nb_moves+1)%2};
+        let mut winning_move = nb_cells+1-2*score.unsigned_abs();
+        if winning_move%2 != winner_parity {
+            winning_move -= 1;
+        }
+        let plies = winning_move+1-nb_moves;
+        if score > 0 {
+            Outcome::Win(plies)
+        } else {
+            Outcome::Loss(plies)
+        }
+    }
+}
+
+// A position as seen from the player to move, 'current' contains the pieces of the
+// player to move and 'mask' all pieces on the board. Uses the same bit layout as Connect4Board.
+#[derive(Clone, Copy)]
+struct Position<const W: usize, const H: usize, const K: usize> {
+    current: u128,
+    mask: u128,
+    nb_moves: u32,
+}
+
+impl<const W: usize, const H: usize, const K: usize> Position<W, H, K> {
+    const COLUMN_BITS: usize = H+1;
+    const BOTTOM_MASK: u128 = Self::bottom_mask();
+    const BOARD_MASK: u128 = Self::BOTTOM_MASK * ((1<<H)-1);
+
+    const fn bottom_mask() -> u128 {
+        let mut mask = 0;
+        let mut x = 0;
+        while x < W {
+            mask |= 1 << (x*Self::COLUMN_BITS);
+            x += 1;
+        }
+        mask
+    }
+
+    fn column_mask(x: usize) -> u128 {
+        ((1<<H)-1) << (x*Self::COLUMN_BITS)
+    }
+
+    // Returns a mask of the empty cells that would complete K in a row for the player with 'position'.
+    fn winning_cells(position: u128, mask: u128) -> u128 {
+        let mut r = 0;
+        // vertical, horizontal and the two diagonals.
+        for step in [1, Self::COLUMN_BITS, Self::COLUMN_BITS-1, Self::COLUMN_BITS+1] {
+            // lower[i] are the cells with i pieces in a row next to them towards the lower bits
+            // and higher[i] the same towards the higher bits.
+            let mut lower = [Self::BOARD_MASK; K];
+            let mut higher = [Self::BOARD_MASK; K];
+            for i in 1..K {
+                lower[i] = lower[i-1] & Self::shift(position, (i*step) as i32);
+                higher[i] = higher[i-1] & Self::shift(position, -((i*step) as i32));
+            }
+            for i in 0..K {
+                r |= lower[i] & higher[K-1-i];
+            }
+        }
+        r & (Self::BOARD_MASK ^ mask)
+    }
+
+    // Shifts towards the higher bits when 'n' is positive, the bits shifted out of the u128 are dropped.
+    fn shift(bitboard: u128, n: i32) -> u128 {
+        if n >= 0 {
+            bitboard.checked_shl(n as u32).unwrap_or(0)
+        } else {
+            bitboard.checked_shr(n.unsigned_abs()).unwrap_or(0)
+        }
+    }
+
+    fn from_board(board: &Connect4Board<W, H, K>) -> Self {
+        Position {
+            current: board.bitboards[board.cur_player as usize-1],
+            mask: board.bitboards[0] | board.bitboards[1],
+            nb_moves: board.nb_moves,
+        }
+    }
+
+    // unique for every position.
+    fn key(&self) -> u128 {
+        self.current + self.mask
+    }
+
+    fn possible(&self) -> u128 {
+        (self.mask + Self::BOTTOM_MASK) & Self::BOARD_MASK
+    }
+
+    fn play(&mut self, mv: u128) {
+        self.current ^= self.mask;
+        self.mask |= mv;
+        self.nb_moves += 1;
+    }
+
+    fn can_win_next(&self) -> bool {
+        Self::winning_cells(self.current, self.mask) & self.possible() != 0
+    }
+
+    // Moves that do not give the opponent an immediate win.
+    // Assumes that the player to move can not win directly.
+    fn non_losing_moves(&self) -> u128 {
+        let mut possible = self.possible();
+        let opponent_win = Self::winning_cells(self.current ^ self.mask, self.mask);
+        let forced = possible & opponent_win;
+        if forced != 0 {
+            if forced & (forced-1) != 0 {
+                // the opponent has two threats, we can only block one.
+                return 0;
+            }
+            possible = forced;
+        }
+        // never play directly below a cell where the opponent would win.
+        possible & !(opponent_win >> 1)
+    }
+
+    // Number of threats the player to move has after playing 'mv', used for move ordering.
+    fn move_score(&self, mv: u128) -> u32 {
+        Self::winning_cells(self.current | mv, self.mask).count_ones()
+    }
+}
+
+// Stores a single bound per position, the value encodes whether it is a lower or an upper bound.
+// Only the low 64 bits of a key are stored, together with the index, key%size, they still tell
+// the keys apart as long as the keys are below size*2^64 since the size is odd. The keys of
+// boards with W*(H+1) up to 64+log2(size) bits are below that, larger boards may share entries.
+struct TranspositionTable {
+    keys: Vec<u64>,
+    values: Vec<i8>,
+}
+
+impl TranspositionTable {
+    fn new(size: usize) -> TranspositionTable {
+        TranspositionTable {
+            keys: vec![0; size],
+            values: vec![0; size],
+        }
+    }
+
+    fn get(&self, key: u128) -> Option<i8> {
+        let i = (key%self.keys.len() as u128) as usize;
+        if self.keys[i] == key as u64 && self.values[i] != 0 {
+            Some(self.values[i])
+        } else {
+            None
+        }
+    }
+
+    fn set(&mut self, key: u128, value: i8) {
+        let i = (key%self.keys.len() as u128) as usize;
+        self.keys[i] = key as u64;
+        self.values[i] = value;
+    }
+
+    fn clear(&mut self) {
+        self.keys.iter_mut().for_each(|k| *k = 0);
+        self.values.iter_mut().for_each(|v| *v = 0);
+    }
+}
+
+// Computes game theoretic values of positions on a W by H board where K in a row wins.
+// The transposition table is kept between calls so solving positions from the same game gets faster.
+pub struct Solver<const W: usize = BOARD_WIDTH, const H: usize = BOARD_HEIGHT, const K: usize = 4> {
+    tt: TranspositionTable,
+    pub node_count: u64,
+}
+
+impl<const W: usize, const H: usize, const K: usize> Solver<W, H, K> {
+    // a player wins at the earliest with their K:th piece.
+    pub const MIN_SCORE: i32 = -((W*H) as i32)/2 + K as i32-1;
+    pub const MAX_SCORE: i32 = ((W*H) as i32 + 1)/2 - (K as i32-1);
+    // The bounds are stored as an i8 in the transposition table, which limits the number of cells.
+    // Boards where they do not fit fail to compile.
+    const NB_CELLS: u32 = {
+        assert!(2*(Self::MAX_SCORE-Self::MIN_SCORE)+2 <= i8::MAX as i32, "the scores do not fit in the transposition table");
+        (W*H) as u32
+    };
+
+    pub fn new() -> Self {
+        Solver::with_table_size(DEFAULT_TABLE_SIZE)
+    }
+
+    // 'size' should preferably be a prime number.
+    pub fn with_table_size(size: usize) -> Self {
+        Solver {
+            tt: TranspositionTable::new(size),
+            node_count: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.tt.clear();
+        self.node_count = 0;
+    }
+
+    // Returns the exact score of 'board' for the player to move, see Self::MIN_SCORE and Self::MAX_SCORE.
+    pub fn score(&mut self, board: &Connect4Board<W, H, K>) -> i32 {
+        match board.game_state() {
+            // the previous move won the game.
+            GameState::Won(_) => return -(((Self::NB_CELLS+2-board.nb_moves)/2) as i32),
+            GameState::Draw => return 0,
+            GameState::InProgress => {},
+        }
+        let position = Position::from_board(board);
+        if position.can_win_next() {
+            return ((Self::NB_CELLS+1-position.nb_moves)/2) as i32;
+        }
+
+        // Iterative deepening with null windows, the windows start close to 0 and the extreme
+        // scores so that draws and short wins are proven before searching the whole range.
+        let mut min = -(((Self::NB_CELLS-position.nb_moves)/2) as i32);
+        let mut max = ((Self::NB_CELLS+1-position.nb_moves)/2) as i32;
+        while min < max {
+            let mut med = min+(max-min)/2;
+            if med <= 0 && min/2 < med {
+                med = min/2;
+            } else if med >= 0 && max/2 > med {
+                med = max/2;
+            }
+            let r = self.negamax(&position, med, med+1);
+            if r <= med {
+                max = r;
+            } else {
+                min = r;
+            }
+        }
+        min
+    }
+
+    pub fn solve(&mut self, board: &Connect4Board<W, H, K>) -> Outcome {
+        Outcome::from_score(self.score(board), board)
+    }
+
+    // Returns the score of every legal action from the perspective of the player to move.
+    pub fn action_scores(&mut self, board: &Connect4Board<W, H, K>) -> Vec<(Action, i32)> {
+        let mut scores = Vec::with_capacity(W);
+        if board.game_state() != GameState::InProgress {
+            return scores;
+        }
+        for action in 0..W {
+            if !board.is_valid_move(action) {
+                continue;
+            }
+            let mut child = *board;
+            child.play_action(action);
+            scores.push((action, -self.score(&child)));
+        }
+        scores
+    }
+
+    // Returns one of the actions with the highest score.
+    pub fn best_action(&mut self, board: &Connect4Board<W, H, K>) -> Action {
+        let scores = self.action_scores(board);
+        scores.iter().max_by_key(|(_, score)| *score).expect("game is in progress").0
+    }
+
+    // Null window negamax, assumes that the player to move can not win directly.
+    // Returns the exact score if it is in [alpha, beta], otherwise a bound.
+    fn negamax(&mut self, position: &Position<W, H, K>, mut alpha: i32, mut beta: i32) -> i32 {
+        self.node_count += 1;
+
+        let next = position.non_losing_moves();
+        if next == 0 {
+            // every move lets the opponent win.
+            return -(((Self::NB_CELLS-position.nb_moves)/2) as i32);
+        }
+        if position.nb_moves >= Self::NB_CELLS-2 {
+            // neither player can win with the last two pieces.
+            return 0;
+        }
+
+        // the opponent can not win with its next piece.
+        let min = -(((Self::NB_CELLS-2-position.nb_moves)/2) as i32);
+        if alpha < min {
+            alpha = min;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+
+        // we can not win with our next piece.
+        let mut max = ((Self::NB_CELLS-1-position.nb_moves)/2) as i32;
+        if let Some(v) = self.tt.get(position.key()) {
+            let v = v as i32;
+            if v > Self::MAX_SCORE-Self::MIN_SCORE+1 {
+                let min = v+2*Self::MIN_SCORE-Self::MAX_SCORE-2;
+                if alpha < min {
+                    alpha = min;
+                    if alpha >= beta {
+                        return alpha;
+                    }
+                }
+            } else {
+                max = v+Self::MIN_SCORE-1;
+            }
+        }
+        if beta > max {
+            beta = max;
+            if alpha >= beta {
+                return beta;
+            }
+        }
+
+        // center columns first and then the moves creating the most threats.
+        let mut moves = [(0u128, 0u32); W];
+        let mut nb_moves = 0;
+        for i in 0..W {
+            let x = (W as i32/2 + (1-2*(i as i32%2))*(i as i32+1)/2) as usize;
+            let mv = next & Position::<W, H, K>::column_mask(x);
+            if mv != 0 {
+                let score = position.move_score(mv);
+                let mut j = nb_moves;
+                while j > 0 && moves[j-1].1 < score {
+                    moves[j] = moves[j-1];
+                    j -= 1;
+                }
+                moves[j] = (mv, score);
+                nb_moves += 1;
+            }
+        }
+
+        for &(mv, _) in &moves[..nb_moves] {
+            let mut child = *position;
+            child.play(mv);
+            let score = -self.negamax(&child, -beta, -alpha);
+            if score >= beta {
+                self.tt.set(position.key(), (score+Self::MAX_SCORE-2*Self::MIN_SCORE+2) as i8);
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+        self.tt.set(position.key(), (alpha-Self::MIN_SCORE+1) as i8);
+        alpha
+    }
+}
+
+impl<const W: usize, const H: usize, const K: usize> Default for Solver<W, H, K> {
+    fn default() -> Self {
+        Solver::new()
+    }
+}
+
+// Solves 'board' with a fresh solver.
+pub fn solve<const W: usize, const H: usize, const K: usize>(board: &Connect4Board<W, H, K>) -> Outcome {
+    Solver::<W, H, K>::new().solve(board)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::games::Player;
+    use crate::games::connect4::Connect4;
+
+    // Exhaustive search without any pruning.
+    fn brute_force_score<const W: usize, const H: usize, const K: usize>(board: &mut Connect4Board<W, H, K>) -> i32 {
+        let nb_cells = (W*H) as u32;
+        let mut best = -(nb_cells as i32);
+        for action in 0..W {
+            if !board.is_valid_move(action) {
+                continue;
+            }
+            let undo = board.play_action(action);
+            let score = match board.game_state() {
+                GameState::Won(_) => ((nb_cells+2-board.nb_moves)/2) as i32,
+                GameState::Draw => 0,
+                GameState::InProgress => -brute_force_score(board),
+            };
+            board.reverse_last_action(undo);
+            best = best.max(score);
+        }
+        best
+    }
+
+    fn random_position<const W: usize, const H: usize, const K: usize>(nb_moves: u32) -> Connect4Board<W, H, K> {
+        loop {
+            let mut board = Connect4Board::new();
+            while board.nb_moves < nb_moves && board.game_state() == GameState::InProgress {
+                let actions: Vec<_> = (0..W).filter(|a| board.is_valid_move(*a)).collect();
+                board.play_action(actions[fastrand::usize(0..actions.len())]);
+            }
+            if board.game_state() == GameState::InProgress {
+                return board;
+            }
+        }
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        fastrand::seed(7);
+        let mut solver = Solver::with_table_size(100003);
+        for i in 0..40 {
+            let mut board: Connect4 = random_position(32+i%3);
+            let expected = brute_force_score(&mut board);
+            assert_eq!(solver.score(&board), expected, "{:?}", board);
+        }
+    }
+
+    #[test]
+    fn outcomes() {
+        // red can win directly by completing the column.
+        let mut board = Connect4::new();
+        for mv in [0, 1, 0, 1, 0, 1] {
+            board.play_action(mv);
+        }
+        let mut solver = Solver::with_table_size(100003);
+        assert_eq!(solver.solve(&board), Outcome::Win(1));
+        assert_eq!(solver.best_action(&board), 0);
+
+        // yellow has to block and then red wins with the other threat.
+        let mut board = Connect4::new();
+        for mv in [1, 1, 2, 2] {
+            board.play_action(mv);
+        }
+        assert_eq!(solver.solve(&board), Outcome::Win(3));
+        board.play_action(3);
+        assert_eq!(solver.solve(&board), Outcome::Loss(2));
+
+        board.play_action(4);
+        assert_eq!(board.game_state(), GameState::InProgress);
+        board.play_action(0);
+        assert_eq!(board.game_state(), GameState::Won(Player::Red));
+        assert_eq!(solver.solve(&board), Outcome::Loss(0));
+    }
+
+    #[test]
+    fn other_sizes() {
+        fastrand::seed(11);
+        let mut solver = Solver::<5, 4>::with_table_size(100003);
+        for i in 0..20 {
+            let mut board = random_position::<5, 4, 4>(6+i%3);
+            let expected = brute_force_score(&mut board);
+            assert_eq!(solver.score(&board), expected, "{:?}", board);
+        }
+        // red has three in a row on the bottom of an 8 wide board with both ends open.
+        let mut solver = Solver::<8, 7>::with_table_size(100003);
+        let mut board = Connect4Board::<8, 7, 4>::new();
+        for mv in [3, 3, 4, 4, 2] {
+            board.play_action(mv);
+        }
+        assert_eq!(solver.solve(&board), Outcome::Loss(2));
+        assert_eq!(Solver::<8, 7>::MAX_SCORE, 25);
+
+        // the same on a 9x7 board, which takes more than 64 bits.
+        let mut solver = Solver::<9, 7>::with_table_size(100003);
+        let mut board = Connect4Board::<9, 7, 4>::new();
+        for mv in [5, 5, 6, 6, 7] {
+            board.play_action(mv);
+        }
+        assert_eq!(solver.solve(&board), Outcome::Loss(2));
+        board.play_action(8);
+        assert_eq!(solver.solve(&board), Outcome::Win(1));
+
+        // three in a row.
+        let mut solver = Solver::<4, 4, 3>::with_table_size(100003);
+        for i in 0..20 {
+            let mut board = random_position::<4, 4, 3>(6+i%3);
+            let expected = brute_force_score(&mut board);
+            assert_eq!(solver.score(&board), expected, "{:?}", board);
+        }
+        assert_eq!(Solver::<4, 4, 3>::MAX_SCORE, 6);
+    }
+}